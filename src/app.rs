@@ -1,13 +1,28 @@
 use arboard::Clipboard;
+use encoding_rs::Encoding;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
+use std::ops::Range;
+use std::path::Path;
+#[cfg(not(windows))]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-use skia::{AutoCanvasRestore, Color4f, IPoint, IRect, Rect, RoundOut, Surface, Vector};
-use winit::event::{ElementState, Modifiers};
+use skia::{
+    AutoCanvasRestore, Color4f, Font, FontStyle, IPoint, IRect, Paint, Rect, RoundOut, Surface,
+    Vector,
+};
+use winit::event::{ElementState, Modifiers, MouseScrollDelta, TouchPhase};
 use winit::keyboard::{ModifiersState, NamedKey};
 use winit::window::CursorIcon;
 
-use crate::editor::{Editor, Movement, PaintOpts, TextPosition};
+use crate::editor::{EditKind, Editor, Movement, PaintOpts, Selection, TextPosition};
+use crate::error::{Error, Result};
+use crate::file;
+use crate::font_picker::FontPicker;
+use crate::kinetic::{Fling, VelocityTracker};
+use crate::settings::{self, Settings};
+use crate::worker::Shaped;
 
 pub trait Layer {
     fn new() -> Self;
@@ -29,11 +44,42 @@ pub trait Layer {
     ) -> bool {
         false
     }
-    // fn on_mouse_wheel(&mut self, delta: f32, x: i32, y: i32, modifier_key: skui::ModifierKey) -> bool;
-    // fn on_touch(&mut self, owner: isize, input_state: skui::InputState, x: f32, y: f32) -> bool;
-    // fn on_fling(&mut self, state: skui::InputState) -> bool;
-    // fn on_pinch(&mut self, state: skui::InputState, scale: f32, (width, height): (u32, u32)) -> bool;
+    fn on_mouse_move(
+        &mut self,
+        _ctx: &mut Context<'_>,
+        (_x, _y): (i32, i32),
+        _modifiers: Modifiers,
+    ) -> bool {
+        false
+    }
+    fn on_mouse_wheel(
+        &mut self,
+        _ctx: &mut Context<'_>,
+        _delta: MouseScrollDelta,
+        _modifiers: Modifiers,
+    ) -> bool {
+        false
+    }
+    fn on_touch(
+        &mut self,
+        _ctx: &mut Context<'_>,
+        _id: u64,
+        _phase: TouchPhase,
+        (_x, _y): (f32, f32),
+    ) -> bool {
+        false
+    }
+    /// `velocity` is in pixels per second.
+    fn on_fling(&mut self, _ctx: &mut Context<'_>, _velocity: (f32, f32)) -> bool {
+        false
+    }
+    /// `scale` is relative to the previous call.
+    fn on_pinch(&mut self, _ctx: &mut Context<'_>, _scale: f32) -> bool {
+        false
+    }
     // fn on_ui_state_changed(&mut self, state_name: SkString, state_value: SkString);
+    /// called once the time requested with `Context::request_wake_up` has passed.
+    fn on_idle(&mut self, _ctx: &mut Context<'_>) {}
     fn on_pre_paint(&mut self, _ctx: &mut Context<'_>) {}
     fn on_paint(&mut self, _ctx: &mut Context<'_>, _surface: &mut Surface) {}
     fn on_resize(&mut self, _ctx: &mut Context<'_>, (_width, _height): (i32, i32)) {}
@@ -42,6 +88,7 @@ pub trait Layer {
 pub struct Context<'a> {
     pub window: &'a winit::window::Window,
     pub clipboard: Clipboard,
+    pub wake_up: Option<Instant>,
 }
 
 impl Context<'_> {
@@ -60,6 +107,25 @@ impl Context<'_> {
     pub fn set_cursor_icon(&self, icon: CursorIcon) {
         self.window.set_cursor_icon(icon)
     }
+
+    /// Asks for `Layer::on_idle` to be called at `instant`, keeping the earliest request.
+    pub fn request_wake_up(&mut self, instant: Instant) {
+        if self.wake_up.is_none_or(|wake_up| instant < wake_up) {
+            self.wake_up = Some(instant);
+        }
+    }
+}
+
+/// Events sent to the event loop from other threads.
+pub enum UserEvent {
+    /// a paragraph shaped by the `ShapeWorker`.
+    Shaped(Shaped),
+}
+
+/// A message shown over the text until `until`.
+pub struct Notification {
+    pub text: String,
+    pub until: Instant,
 }
 
 // todo: private
@@ -68,38 +134,119 @@ pub struct EditorLayer {
     pub editor: Editor,
     pub text_pos: TextPosition,
     pub mark_pos: Option<TextPosition>,
+    /// caret x kept across repeated Up/Down
+    pub goal_x: Option<f32>,
+    /// x of the caret after a visual Left/Right, since positions where the direction of the
+    /// text changes have two
+    pub caret_x: Option<f32>,
+    /// Left and Right move on screen instead of through the text
+    pub visual_arrows: bool,
     /// window pixel position in file
     pub pos: i32,
+    /// window pixel x position in file, when wrapping is off
+    pub h_pos: i32,
+    /// sub-pixel part of the scroll position, from high-resolution touchpads
+    pub scroll_fraction: (f32, f32),
     /// window width
     pub width: i32,
     /// window height
     pub height: i32,
     pub margin: i32,
+    /// installed font families, sorted; listed on first use
+    pub families: Vec<String>,
+    /// index of the current family in `families`
     pub typeface_index: usize,
+    /// family list opened with Ctrl+Shift+P
+    pub font_picker: Option<FontPicker>,
     pub font_size: f32,
     pub shift_down: bool,
     pub blink: bool,
     pub mouse_down: bool,
+    /// 1 for a single click, 2 for a double click and 3 for a triple click
+    pub click_count: u32,
+    /// time and window position of the last press
+    pub last_click: Option<(Instant, (i32, i32))>,
+    /// word or paragraph selected by a multi-click, which dragging extends
+    pub click_range: Option<Range<TextPosition>>,
+    /// touch points on the screen, by id
+    pub touches: Vec<(u64, (f32, f32))>,
+    /// where a single touch started, until it moves too far to be a tap
+    pub tap_start: Option<(f32, f32)>,
+    pub touch_velocity: VelocityTracker,
+    /// scrolling that continues after a touch drag
+    pub fling: Option<Fling>,
+    /// last pointer position in the window, for auto-scrolling while dragging
+    pub mouse_pos: (i32, i32),
+    /// Backspace removes a trailing combining mark instead of the whole grapheme cluster.
+    pub backspace_code_point: bool,
+    /// path being typed in the "Save As" prompt
+    pub save_as: Option<String>,
+    pub notification: Option<Notification>,
+    pub settings: Settings,
 }
 
 const FONT_SIZE: f32 = 18.;
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(5);
+const AUTO_SCROLL_INTERVAL: Duration = Duration::from_millis(16);
+/// lines scrolled per wheel notch.
+const WHEEL_LINES: f32 = 3.;
+const FLING_INTERVAL: Duration = Duration::from_millis(16);
+/// how far a touch may move and still be a tap.
+const TAP_SLOP: f32 = 8.;
+const MIN_FONT_SIZE: f32 = 6.;
+const MAX_FONT_SIZE: f32 = 144.;
+/// font size factor of one zoom step.
+const ZOOM_STEP: f32 = 1.1;
+const FONT_PICKER_WIDTH: f32 = 600.;
 
 impl Layer for EditorLayer {
     fn new() -> Self {
+        let settings = Settings::load();
+        let mut editor = Editor::new();
+        let typeface = settings.font_family.as_ref().and_then(|family| {
+            editor
+                .font_mgr()
+                .match_family_style(family, FontStyle::default())
+        });
+        let mut font = editor.font().clone();
+        font.set_size(FONT_SIZE);
+        if let Some(typeface) = typeface {
+            font.set_typeface(typeface);
+        }
+        editor.set_font(font);
         Self {
             path: String::new(),
-            editor: Editor::new(),
+            editor,
             text_pos: TextPosition::new(0, 0),
             mark_pos: None,
+            goal_x: None,
+            caret_x: None,
+            visual_arrows: false,
             pos: 0,
+            h_pos: 0,
+            scroll_fraction: (0., 0.),
             width: 0,
             height: 0,
             margin: 10,
+            families: vec![],
             typeface_index: 0,
+            font_picker: None,
             font_size: FONT_SIZE,
             shift_down: false,
             blink: false,
             mouse_down: false,
+            click_count: 0,
+            last_click: None,
+            click_range: None,
+            touches: vec![],
+            tap_start: None,
+            touch_velocity: Default::default(),
+            fling: None,
+            mouse_pos: (0, 0),
+            backspace_code_point: false,
+            save_as: None,
+            notification: None,
+            settings,
         }
     }
 
@@ -110,6 +257,21 @@ impl Layer for EditorLayer {
     fn set_active(&mut self, _ctx: &mut Context<'_>, _active: bool) {}
 
     fn on_char(&mut self, ctx: &mut Context<'_>, c: char, modifiers: Modifiers) -> bool {
+        if let Some(path) = &mut self.save_as {
+            if !modifiers.state().control_key() && !c.is_control() {
+                path.push(c);
+                ctx.invalidate();
+            }
+            return true;
+        }
+        if let Some(picker) = &mut self.font_picker {
+            if !modifiers.state().control_key() && !c.is_control() {
+                picker.push(c);
+                ctx.invalidate();
+            }
+            return true;
+        }
+
         let c = match c {
             '\r' => '\n',
             _ => c,
@@ -117,28 +279,88 @@ impl Layer for EditorLayer {
 
         match modifiers.state() {
             state if state.is_empty() => {
-                let mut buf = [0; 4];
-                self.editor.insert(self.text_pos, c.encode_utf8(&mut buf));
-                self.move_cursor(ctx, Movement::Right, false)
+                let text_pos = self.text_pos;
+                self.edit(ctx, EditKind::Typing, |editor| {
+                    editor.insert(text_pos, c.encode_utf8(&mut [0; 4]))
+                })
             }
+            state if state == ModifiersState::CONTROL | ModifiersState::SHIFT => match c {
+                'z' | 'Z' => self.redo(ctx),
+                's' | 'S' => self.begin_save_as(ctx),
+                'e' | 'E' => self.cycle_encoding(ctx),
+                'p' | 'P' => self.open_font_picker(ctx),
+                // `+` and `_` are typed with shift on most layouts.
+                '=' | '+' => self.zoom(ctx, self.font_size * ZOOM_STEP),
+                '-' | '_' => self.zoom(ctx, self.font_size / ZOOM_STEP),
+                'l' | 'L' => {
+                    let line_ending = self.editor.format().line_ending;
+                    self.editor.set_line_ending(line_ending.next());
+                    self.update_title(ctx);
+                    true
+                }
+                _ => false,
+            },
+            ModifiersState::ALT => match c {
+                'b' => {
+                    self.backspace_code_point = !self.backspace_code_point;
+                    let unit = if self.backspace_code_point {
+                        "combining mark"
+                    } else {
+                        "grapheme cluster"
+                    };
+                    self.notify(ctx, format!("Backspace removes a {unit}"));
+                    true
+                }
+                'h' => {
+                    let smart_home = !self.editor.is_smart_home();
+                    self.editor.set_smart_home(smart_home);
+                    let state = if smart_home { "on" } else { "off" };
+                    self.notify(ctx, format!("smart Home and End: {state}"));
+                    true
+                }
+                'v' => {
+                    self.visual_arrows = !self.visual_arrows;
+                    self.caret_x = None;
+                    true
+                }
+                'z' => {
+                    self.editor.set_wrap(!self.editor.is_wrapped());
+                    self.h_pos = 0;
+                    self.scroll_to_cursor();
+                    ctx.invalidate();
+                    true
+                }
+                _ => false,
+            },
             ModifiersState::CONTROL => match c {
-                'p' => todo!(),
-                's' => todo!(),
-                'c' => todo!(),
-                'x' => todo!(),
+                'p' => self.cycle_font(ctx),
+                's' if self.path.is_empty() => self.begin_save_as(ctx),
+                's' => {
+                    let result = self.save(ctx);
+                    self.report(ctx, result)
+                }
+                'c' => self.copy(ctx),
+                'x' => {
+                    if !self.copy(ctx) {
+                        return false;
+                    }
+                    let range = TextPosition::range(self.mark_pos.unwrap(), self.text_pos);
+                    self.edit(ctx, EditKind::Other, |editor| editor.remove(range))
+                }
                 'v' => match ctx.get_clipboard_text() {
                     Some(text) => {
-                        self.text_pos = self.editor.insert(self.text_pos, &text);
-                        ctx.invalidate();
-                        true
+                        let text_pos = self.text_pos;
+                        self.edit(ctx, EditKind::Other, |editor| {
+                            editor.insert(text_pos, &file::normalize_line_endings(&text))
+                        })
                     }
                     None => false,
                 },
-                '0' => todo!(),
-                '=' => todo!(),
-                '+' => todo!(),
-                '-' => todo!(),
-                '_' => todo!(),
+                'z' => self.undo(ctx),
+                'y' => self.redo(ctx),
+                '0' => self.zoom(ctx, FONT_SIZE),
+                '=' | '+' => self.zoom(ctx, self.font_size * ZOOM_STEP),
+                '-' | '_' => self.zoom(ctx, self.font_size / ZOOM_STEP),
                 _ => false,
             },
             _ => false,
@@ -148,32 +370,84 @@ impl Layer for EditorLayer {
     fn on_key(&mut self, ctx: &mut Context<'_>, key: NamedKey, modifiers: Modifiers) -> bool {
         use NamedKey::*;
 
+        if let Some(path) = &mut self.save_as {
+            match key {
+                Enter => {
+                    let path = self.save_as.take().unwrap();
+                    if !path.is_empty() {
+                        self.path = path;
+                        let result = self.save(ctx);
+                        self.report(ctx, result);
+                    }
+                }
+                Escape => self.save_as = None,
+                Backspace => {
+                    path.pop();
+                }
+                Space => path.push(' '),
+                _ => {}
+            }
+            ctx.invalidate();
+            return true;
+        }
+        if let Some(picker) = &mut self.font_picker {
+            match key {
+                Enter => {
+                    let selected = picker.selected();
+                    self.font_picker = None;
+                    if let Some(index) = selected {
+                        self.typeface_index = index;
+                        self.set_font(ctx);
+                    }
+                }
+                Escape => self.font_picker = None,
+                ArrowUp => picker.move_by(-1),
+                ArrowDown => picker.move_by(1),
+                PageUp => picker.move_by_pages(-1),
+                PageDown => picker.move_by_pages(1),
+                Backspace => picker.pop(),
+                Space => picker.push(' '),
+                _ => {}
+            }
+            ctx.invalidate();
+            return true;
+        }
+
         let shift = modifiers.state().shift_key();
+        let control = modifiers.state().control_key();
+        let backspace_code_point = self.backspace_code_point;
 
         let mut delete = |mov| {
-            let pos = match self.mark_pos {
-                None => self.editor.mov(mov, self.text_pos),
-                Some(mark_pos) => mark_pos,
+            self.editor.shape_paragraph(self.text_pos.paragraph_index);
+            let (kind, pos) = match self.mark_pos {
+                None => (EditKind::Delete, self.editor.mov(mov, self.text_pos)),
+                Some(mark_pos) => (EditKind::Other, mark_pos),
             };
-            let pos = self.editor.remove(TextPosition::range(pos, self.text_pos));
-            self.mov(ctx, pos, shift);
-            ctx.invalidate();
-            true
+            let text_pos = self.text_pos;
+            self.edit(ctx, kind, |editor| {
+                editor.remove(TextPosition::range(pos, text_pos))
+            })
         };
 
         match key {
-            ArrowLeft => self.move_cursor(ctx, Movement::Left, shift),
-            ArrowRight => self.move_cursor(ctx, Movement::Right, shift),
+            ArrowLeft | ArrowRight => {
+                let mov = self.arrow_movement(key == ArrowRight, control);
+                self.move_cursor(ctx, mov, shift)
+            }
             ArrowUp => self.move_cursor(ctx, Movement::Up, shift),
             ArrowDown => self.move_cursor(ctx, Movement::Down, shift),
+            Home if control => self.move_cursor(ctx, Movement::DocumentStart, shift),
+            End if control => self.move_cursor(ctx, Movement::DocumentEnd, shift),
             Home => self.move_cursor(ctx, Movement::Home, shift),
             End => self.move_cursor(ctx, Movement::End, shift),
+            Delete if control => delete(Movement::WordRight),
+            Backspace if control => delete(Movement::WordLeft),
             Delete => delete(Movement::Right),
+            Backspace if backspace_code_point => delete(Movement::CodePointLeft),
             Backspace => delete(Movement::Left),
             Enter => self.on_char(ctx, '\n', modifiers),
             _ => false,
         }
-        // todo: Ctrl+left,right
     }
 
     fn on_mouse(
@@ -186,14 +460,28 @@ impl Layer for EditorLayer {
         match input_state {
             ElementState::Pressed => {
                 self.mouse_down = true;
-                match self
-                    .editor
-                    .get_position(IPoint::new(x - self.margin, y + self.pos - self.margin))
-                {
-                    //
-                    Some(pos) => self.mov(ctx, pos, modifiers.state().shift_key()),
-                    None => false,
+                self.count_click((x, y));
+                self.reshape();
+                let pos = match self.editor.get_position(IPoint::new(
+                    x + self.h_pos - self.margin,
+                    y + self.pos - self.margin,
+                )) {
+                    Some(pos) => pos,
+                    None => return false,
+                };
+                if self.click_count == 1 {
+                    self.click_range = None;
+                    if modifiers.state().shift_key() {
+                        return self.mov(ctx, pos, true);
+                    }
+                    // also when pressed at the caret, so that a drag from there selects.
+                    self.set_selection(ctx, Selection::new(pos, None));
+                    return true;
                 }
+                let range = self.click_unit(pos);
+                self.click_range = Some(range.clone());
+                self.set_selection(ctx, Selection::new(range.end, Some(range.start)));
+                true
             }
             ElementState::Released => {
                 self.mouse_down = false;
@@ -202,7 +490,129 @@ impl Layer for EditorLayer {
         }
     }
 
+    fn on_mouse_wheel(
+        &mut self,
+        ctx: &mut Context<'_>,
+        delta: MouseScrollDelta,
+        modifiers: Modifiers,
+    ) -> bool {
+        if modifiers.state().control_key() {
+            let steps = match delta {
+                MouseScrollDelta::LineDelta(_, y) => y,
+                MouseScrollDelta::PixelDelta(delta) => {
+                    delta.y as f32 / (self.editor.font().spacing() * WHEEL_LINES)
+                }
+            };
+            return self.zoom(ctx, self.font_size * ZOOM_STEP.powf(steps));
+        }
+        // winit's deltas are positive when the content should move right or down.
+        let (x, y) = match delta {
+            MouseScrollDelta::LineDelta(x, y) => {
+                let line = self.editor.font().spacing() * WHEEL_LINES;
+                (-x * line, -y * line)
+            }
+            MouseScrollDelta::PixelDelta(delta) => (-delta.x as f32, -delta.y as f32),
+        };
+        if modifiers.state().shift_key() && x == 0. && !self.editor.is_wrapped() {
+            self.scroll(ctx, (y, 0.))
+        } else {
+            self.scroll(ctx, (x, y))
+        }
+    }
+
+    fn on_touch(
+        &mut self,
+        ctx: &mut Context<'_>,
+        id: u64,
+        phase: TouchPhase,
+        (x, y): (f32, f32),
+    ) -> bool {
+        let now = Instant::now();
+        match phase {
+            TouchPhase::Started => {
+                self.fling = None;
+                self.touches.push((id, (x, y)));
+                self.touch_velocity.clear();
+                self.touch_velocity.add(now, (x, y));
+                self.tap_start = (self.touches.len() == 1).then_some((x, y));
+                false
+            }
+            TouchPhase::Moved => {
+                let Some(index) = self.touches.iter().position(|&(i, _)| i == id) else {
+                    return false;
+                };
+                let (old_x, old_y) = std::mem::replace(&mut self.touches[index].1, (x, y));
+                match self.touches[..] {
+                    [_] => {
+                        self.touch_velocity.add(now, (x, y));
+                        if let Some((start_x, start_y)) = self.tap_start {
+                            if (x - start_x).hypot(y - start_y) > TAP_SLOP {
+                                self.tap_start = None;
+                            }
+                        }
+                        // the text follows the finger.
+                        self.scroll(ctx, (old_x - x, old_y - y))
+                    }
+                    [(_, a), (_, b)] => {
+                        let (other_x, other_y) = if index == 0 { b } else { a };
+                        let before = (old_x - other_x).hypot(old_y - other_y);
+                        let after = (x - other_x).hypot(y - other_y);
+                        before > 0. && self.on_pinch(ctx, after / before)
+                    }
+                    _ => false,
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.retain(|&(i, _)| i != id);
+                if !self.touches.is_empty() || phase == TouchPhase::Cancelled {
+                    self.tap_start = None;
+                    return false;
+                }
+                if self.tap_start.take().is_some() {
+                    let xy = (x as i32, y as i32);
+                    let modifiers = Modifiers::default();
+                    let moved = self.on_mouse(ctx, xy, ElementState::Pressed, modifiers);
+                    self.on_mouse(ctx, xy, ElementState::Released, modifiers);
+                    return moved;
+                }
+                let (velocity_x, velocity_y) = self.touch_velocity.velocity(now);
+                self.on_fling(ctx, (-velocity_x, -velocity_y))
+            }
+        }
+    }
+
+    fn on_fling(&mut self, ctx: &mut Context<'_>, velocity: (f32, f32)) -> bool {
+        let now = Instant::now();
+        self.fling = Fling::new(velocity, now);
+        if self.fling.is_none() {
+            return false;
+        }
+        ctx.request_wake_up(now + FLING_INTERVAL);
+        true
+    }
+
+    fn on_pinch(&mut self, ctx: &mut Context<'_>, scale: f32) -> bool {
+        self.zoom(ctx, self.font_size * scale)
+    }
+
+    fn on_mouse_move(
+        &mut self,
+        ctx: &mut Context<'_>,
+        xy: (i32, i32),
+        _modifiers: Modifiers,
+    ) -> bool {
+        self.mouse_pos = xy;
+        if !self.mouse_down {
+            return false;
+        }
+        if self.auto_scroll_speed() != 0 {
+            ctx.request_wake_up(Instant::now() + AUTO_SCROLL_INTERVAL);
+        }
+        self.drag(ctx)
+    }
+
     fn on_paint(&mut self, _ctx: &mut Context<'_>, surface: &mut Surface) {
+        self.reshape();
         let canvas = surface.canvas();
         let acr = AutoCanvasRestore::guard(canvas, true);
         canvas
@@ -211,7 +621,10 @@ impl Layer for EditorLayer {
                 None,
                 None,
             )
-            .translate(Vector::new(self.margin as _, (self.margin - self.pos) as _));
+            .translate(Vector::new(
+                (self.margin - self.h_pos) as _,
+                (self.margin - self.pos) as _,
+            ));
         let alpha = if self.blink { 0. } else { 1. };
         let mut options = PaintOpts {
             cursor: Some(self.text_pos),
@@ -224,11 +637,106 @@ impl Layer for EditorLayer {
         }
         self.editor.paint(canvas, options);
         drop(acr);
+
+        if let Some(path) = &self.save_as {
+            let font = self.editor.font();
+            let (_, metrics) = font.metrics();
+            let margin = self.margin as f32;
+            let bar_height = font.spacing().ceil() + margin;
+            let bar = Rect::from_xywh(
+                0.,
+                self.height as f32 - bar_height,
+                self.width as _,
+                bar_height,
+            );
+            canvas.draw_rect(bar, &Paint::new(Color4f::new(0.2, 0.2, 0.2, 1.), None));
+            canvas.draw_str(
+                format!("Save as: {path}"),
+                (margin, bar.bottom - margin / 2. - metrics.descent),
+                font,
+                &Paint::new(Color4f::new(1., 1., 1., 1.), None),
+            );
+        }
+
+        if let Some(picker) = &mut self.font_picker {
+            let margin = self.margin as f32;
+            let width = (self.width as f32 - margin * 2.).min(FONT_PICKER_WIDTH);
+            picker.paint(
+                canvas,
+                self.editor.font_mgr(),
+                self.editor.font(),
+                ((self.width as f32 - width) / 2., margin),
+                width,
+                margin,
+            );
+        }
+
+        if let Some(notification) = &self.notification {
+            let font = self.editor.font();
+            let (_, metrics) = font.metrics();
+            let margin = self.margin as f32;
+            let (text_width, _) = font.measure_str(&notification.text, None);
+            let bounds = Rect::from_xywh(
+                self.width as f32 - text_width - margin * 3.,
+                margin,
+                text_width + margin * 2.,
+                font.spacing().ceil() + margin,
+            );
+            canvas.draw_round_rect(
+                bounds,
+                margin / 2.,
+                margin / 2.,
+                &Paint::new(Color4f::new(0.6, 0.1, 0.1, 0.9), None),
+            );
+            canvas.draw_str(
+                &notification.text,
+                (
+                    bounds.left + margin,
+                    bounds.bottom - margin / 2. - metrics.descent,
+                ),
+                font,
+                &Paint::new(Color4f::new(1., 1., 1., 1.), None),
+            );
+        }
+    }
+
+    fn on_idle(&mut self, ctx: &mut Context<'_>) {
+        let now = Instant::now();
+        match self.fling.as_mut().and_then(|fling| fling.step(now)) {
+            Some((x, y)) => {
+                // steps of less than a pixel only add up the fraction, which isn't blocked yet.
+                let (fraction_x, fraction_y) = self.scroll_fraction;
+                let whole = (fraction_x + x).abs() >= 1. || (fraction_y + y).abs() >= 1.;
+                if self.scroll(ctx, (x, y)) || !whole {
+                    ctx.request_wake_up(now + FLING_INTERVAL);
+                } else {
+                    // stopped at an edge of the document.
+                    self.fling = None;
+                }
+            }
+            None => self.fling = None,
+        }
+        let speed = self.auto_scroll_speed();
+        if self.mouse_down && speed != 0 {
+            self.set_pos(self.pos + speed);
+            self.drag(ctx);
+            ctx.invalidate();
+            ctx.request_wake_up(Instant::now() + AUTO_SCROLL_INTERVAL);
+        }
+        if let Some(notification) = &self.notification {
+            if notification.until <= Instant::now() {
+                self.notification = None;
+                ctx.invalidate();
+            } else {
+                ctx.request_wake_up(notification.until);
+            }
+        }
     }
 
     fn on_resize(&mut self, ctx: &mut Context<'_>, size @ (width, height): (i32, i32)) {
         if (self.width, self.height) != size {
             self.height = height;
+            self.editor.set_viewport(self.pos..self.pos + height);
             if self.width != width {
                 self.width = width;
                 self.editor.set_width(width - self.margin * 2);
@@ -239,24 +747,353 @@ impl Layer for EditorLayer {
 }
 
 impl EditorLayer {
-    fn set_font(&mut self, _ctx: &mut Context<'_>) {
-        todo!()
+    /// Switches to the family at `typeface_index`, keeping the size, and remembers it for the
+    /// next session.
+    fn set_font(&mut self, ctx: &mut Context<'_>) -> bool {
+        let family = &self.families[self.typeface_index];
+        let Some(typeface) = self
+            .editor
+            .font_mgr()
+            .match_family_style(family, FontStyle::default())
+        else {
+            let text = format!("{family}: font not found");
+            self.notify(ctx, text);
+            return false;
+        };
+        self.settings.font_family = Some(family.clone());
+        let mut font = self.editor.font().clone();
+        font.set_typeface(typeface);
+        self.replace_font(ctx, font);
+        if let Err(e) = self.settings.save() {
+            self.notify(ctx, format!("settings: {e}"));
+        }
+        true
+    }
+
+    /// Lists the installed font families the first time they are needed.
+    fn load_families(&mut self) {
+        if !self.families.is_empty() {
+            return;
+        }
+        let mut families: Vec<String> = self.editor.font_mgr().family_names().collect();
+        families.sort_by_key(|family| family.to_lowercase());
+        families.dedup();
+        let current = self.editor.font().typeface().family_name();
+        self.typeface_index = families
+            .iter()
+            .position(|family| *family == current)
+            .unwrap_or(0);
+        self.families = families;
+    }
+
+    fn cycle_font(&mut self, ctx: &mut Context<'_>) -> bool {
+        self.load_families();
+        if self.families.is_empty() {
+            return false;
+        }
+        self.typeface_index = (self.typeface_index + 1) % self.families.len();
+        self.set_font(ctx)
     }
 
-    fn load_file(&mut self, _ctx: &mut Context<'_>, path: &str) {
+    fn open_font_picker(&mut self, ctx: &mut Context<'_>) -> bool {
+        self.load_families();
+        self.font_picker = Some(FontPicker::new(self.families.clone(), self.typeface_index));
+        ctx.invalidate();
+        true
+    }
+
+    pub fn on_shaped(&mut self, ctx: &mut Context<'_>, shaped: Shaped) {
+        if self.editor.apply_shaped(shaped) {
+            ctx.invalidate();
+        }
+    }
+
+    /// Opens `path`, or stdin for `-`, and puts the caret at the 1-based `line` and `column`.
+    /// A path that does not exist yet starts an empty buffer that is saved to it.
+    pub fn open(&mut self, ctx: &mut Context<'_>, path: &str, (line, column): (usize, usize)) {
+        let result = match path {
+            "-" => self.load_stdin(ctx),
+            _ => match self.load_file(ctx, path, None) {
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                    self.path = path.to_string();
+                    self.update_title(ctx);
+                    Ok(())
+                }
+                result => result,
+            },
+        };
+        self.report(ctx, result);
+        self.go_to(ctx, line, column);
+    }
+
+    fn load_stdin(&mut self, ctx: &mut Context<'_>) -> Result<()> {
+        self.editor.load(io::stdin().lock(), None)?;
+        // nothing on disk holds this text yet.
+        self.editor.set_dirty(true);
+        self.path.clear();
+        self.set_selection(ctx, Selection::new(TextPosition::new(0, 0), None));
+        self.update_title(ctx);
+        Ok(())
+    }
+
+    fn go_to(&mut self, ctx: &mut Context<'_>, line: usize, column: usize) {
+        let paragraph_index = line.saturating_sub(1);
+        let text_byte_index = self.editor.line(paragraph_index).map_or(0, |text| {
+            text.char_indices()
+                .nth(column.saturating_sub(1))
+                .map_or(text.len(), |(i, _)| i)
+        });
+        let pos = TextPosition::new(text_byte_index, paragraph_index);
+        let pos = self.editor.mov(Movement::Nowhere, pos);
+        self.set_selection(ctx, Selection::new(pos, None));
+    }
+
+    fn load_file(
+        &mut self,
+        ctx: &mut Context<'_>,
+        path: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<()> {
+        self.editor
+            .load(BufReader::new(File::open(path)?), encoding)?;
         self.path = path.to_string();
-        self.editor.load(BufReader::new(File::open(path).unwrap()));
+        self.set_selection(ctx, Selection::new(TextPosition::new(0, 0), None));
+        self.update_title(ctx);
+        Ok(())
+    }
+
+    /// Switches to the next encoding. An unmodified file is reloaded with it, otherwise the text
+    /// is converted on the next save.
+    fn cycle_encoding(&mut self, ctx: &mut Context<'_>) -> bool {
+        let encodings = file::encodings();
+        let current = self.editor.format().encoding;
+        let next = encodings
+            .iter()
+            .position(|&encoding| encoding == current)
+            .map_or(0, |i| (i + 1) % encodings.len());
+        if !self.path.is_empty() && !self.editor.is_dirty() {
+            let path = self.path.clone();
+            let result = self.load_file(ctx, &path, Some(encodings[next]));
+            self.report(ctx, result);
+        } else {
+            self.editor.set_encoding(encodings[next]);
+            self.update_title(ctx);
+        }
+        true
+    }
+
+    fn save(&mut self, ctx: &mut Context<'_>) -> Result<()> {
+        let mut data = vec![];
+        self.editor.save(&mut data)?;
+        file::write_atomic(Path::new(&self.path), &data)?;
+        self.editor.set_dirty(false);
+        self.update_title(ctx);
+        Ok(())
+    }
+
+    /// Shows a failed file operation as a notification instead of aborting.
+    fn report(&mut self, ctx: &mut Context<'_>, result: Result<()>) -> bool {
+        if let Err(e) = result {
+            let path = if self.path.is_empty() {
+                "untitled"
+            } else {
+                &self.path
+            };
+            self.notify(ctx, format!("{path}: {e}"));
+        }
+        true
+    }
+
+    fn notify(&mut self, ctx: &mut Context<'_>, text: String) {
+        let until = Instant::now() + NOTIFICATION_DURATION;
+        self.notification = Some(Notification { text, until });
+        ctx.request_wake_up(until);
+        ctx.invalidate();
     }
 
-    fn scroll(delta: u32) -> bool {
-        todo!()
+    fn begin_save_as(&mut self, ctx: &mut Context<'_>) -> bool {
+        self.save_as = Some(self.path.clone());
+        ctx.invalidate();
+        true
+    }
+
+    fn update_title(&self, ctx: &mut Context<'_>) {
+        let name = match Path::new(&self.path).file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => "untitled".into(),
+        };
+        let dirty = if self.editor.is_dirty() { "*" } else { "" };
+        let format = self.editor.format().name();
+        ctx.window.set_title(&format!(
+            "{dirty}{name} [{format}] - skia-plain-text-editor"
+        ));
+    }
+
+    /// Rebuilds the font at `font_size`, within `MIN_FONT_SIZE` and `MAX_FONT_SIZE`.
+    fn zoom(&mut self, ctx: &mut Context<'_>, font_size: f32) -> bool {
+        let font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        if font_size == self.font_size {
+            return false;
+        }
+        let Some(font) = self.editor.font().with_size(font_size) else {
+            return false;
+        };
+        self.font_size = font_size;
+        self.replace_font(ctx, font)
+    }
+
+    /// Sets the editor's font, keeping the caret's line where it is on screen.
+    fn replace_font(&mut self, ctx: &mut Context<'_>, font: Font) -> bool {
+        // keep the caret's line where it is on screen while the text reflows.
+        self.reshape();
+        let caret_y = self
+            .editor
+            .get_location(self.text_pos)
+            .map(|rect| rect.top as i32 - self.pos)
+            .filter(|y| (0..self.height).contains(y));
+        self.editor.set_font(font);
+        match caret_y {
+            Some(y) => {
+                let paragraph_index = self.text_pos.paragraph_index;
+                self.editor.shape_paragraphs_before(paragraph_index, y);
+                if let Some(rect) = self.editor.get_location(self.text_pos) {
+                    self.set_pos(rect.top as i32 - y);
+                }
+            }
+            None => self.scroll_to_cursor(),
+        }
+        ctx.invalidate();
+        true
+    }
+
+    /// Scrolls by `delta` pixels, keeping fractions of a pixel for the next call.
+    fn scroll(&mut self, ctx: &mut Context<'_>, delta: (f32, f32)) -> bool {
+        let (x, y) = (
+            self.scroll_fraction.0 + delta.0,
+            self.scroll_fraction.1 + delta.1,
+        );
+        self.scroll_fraction = (x.fract(), y.fract());
+        let old = (self.h_pos, self.pos);
+        self.set_pos(self.pos + y as i32);
+        if !self.editor.is_wrapped() {
+            let max = self.editor.content_width() + self.margin * 2 - self.width;
+            self.h_pos = (self.h_pos + x as i32).min(max).max(0);
+        }
+        if (self.h_pos, self.pos) == old {
+            return false;
+        }
+        ctx.invalidate();
+        true
+    }
+
+    fn selection(&self) -> Selection {
+        Selection::new(self.text_pos, self.mark_pos)
+    }
+
+    fn set_selection(&mut self, ctx: &mut Context<'_>, selection: Selection) {
+        self.text_pos = selection.cursor;
+        self.mark_pos = selection.mark;
+        self.shift_down = selection.mark.is_some();
+        self.goal_x = None;
+        self.caret_x = None;
+        self.scroll_to_cursor();
+        ctx.invalidate();
+    }
+
+    /// Runs `f` as one undo step and moves the cursor to the position it returns.
+    fn edit(
+        &mut self,
+        ctx: &mut Context<'_>,
+        kind: EditKind,
+        f: impl FnOnce(&mut Editor) -> TextPosition,
+    ) -> bool {
+        self.editor.begin_transaction(kind, self.selection());
+        let pos = f(&mut self.editor);
+        self.set_selection(ctx, Selection::new(pos, None));
+        self.editor.end_transaction(self.selection());
+        self.update_title(ctx);
+        true
+    }
+
+    fn copy(&mut self, ctx: &mut Context<'_>) -> bool {
+        match self.mark_pos {
+            Some(mark_pos) if mark_pos != self.text_pos => {
+                let range = TextPosition::range(mark_pos, self.text_pos);
+                let mut text = vec![0; self.editor.copy(range.clone(), None)];
+                self.editor.copy(range, Some(&mut text));
+                ctx.set_clipboard_text(std::str::from_utf8(&text).unwrap());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn undo(&mut self, ctx: &mut Context<'_>) -> bool {
+        match self.editor.undo() {
+            Some(selection) => {
+                self.set_selection(ctx, selection);
+                self.update_title(ctx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn redo(&mut self, ctx: &mut Context<'_>) -> bool {
+        match self.editor.redo() {
+            Some(selection) => {
+                self.set_selection(ctx, selection);
+                self.update_title(ctx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Movement of the Left or Right arrow key. In logical mode it goes back or forward through
+    /// the text in the direction of the caret's paragraph, so that Right goes back in a
+    /// right-to-left one.
+    fn arrow_movement(&self, right: bool, word: bool) -> Movement {
+        if self.visual_arrows && !word {
+            return if right {
+                Movement::VisualRight
+            } else {
+                Movement::VisualLeft
+            };
+        }
+        let forward = right != self.editor.is_rtl(self.text_pos.paragraph_index);
+        match (forward, word) {
+            (true, true) => Movement::WordRight,
+            (false, true) => Movement::WordLeft,
+            (true, false) => Movement::Right,
+            (false, false) => Movement::Left,
+        }
     }
 
     fn move_cursor(&mut self, ctx: &mut Context<'_>, mov: Movement, shift: bool) -> bool {
-        self.mov(ctx, self.editor.mov(mov, self.text_pos), shift)
+        match mov {
+            Movement::Up | Movement::Down => {
+                let (pos, x) = self.editor.mov_vertical(mov, self.text_pos, self.goal_x);
+                let moved = self.mov(ctx, pos, shift);
+                self.goal_x = Some(x);
+                moved
+            }
+            Movement::VisualLeft | Movement::VisualRight => {
+                let (pos, x) = self.editor.mov_visual(mov, self.text_pos, self.caret_x);
+                let moved = self.mov(ctx, pos, shift);
+                self.caret_x = Some(x);
+                moved
+            }
+            _ => {
+                self.editor.shape_paragraph(self.text_pos.paragraph_index);
+                self.mov(ctx, self.editor.mov(mov, self.text_pos), shift)
+            }
+        }
     }
 
     fn mov(&mut self, ctx: &mut Context<'_>, pos: TextPosition, shift: bool) -> bool {
+        self.goal_x = None;
+        self.caret_x = None;
         if pos == self.text_pos {
             if !shift {
                 self.mark_pos = None;
@@ -268,16 +1105,182 @@ impl EditorLayer {
             self.shift_down = shift;
         }
         self.text_pos = pos;
+        self.scroll_to_cursor();
+        ctx.invalidate();
+        true
+    }
 
-        // scroll if needed.
-        let cursor: IRect = self.editor.get_location(self.text_pos).unwrap().round_out();
+    /// Extends the selection to the pointer while the button is held.
+    fn drag(&mut self, ctx: &mut Context<'_>) -> bool {
+        self.reshape();
+        let (x, y) = self.mouse_pos;
+        // outside the window, select up to the edge and leave the rest to auto-scrolling.
+        let y = y.clamp(0, (self.height - 1).max(0)) + self.pos - self.margin;
+        let bottom = (self.editor.get_height() - 1).max(0);
+        let xy = IPoint::new(x + self.h_pos - self.margin, y.clamp(0, bottom));
+        let pos = match self.editor.get_position(xy) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        let Some(clicked) = self.click_range.clone() else {
+            return self.mov(ctx, pos, true);
+        };
+        // extend by whole words or paragraphs, keeping the clicked one selected.
+        let range = self.click_unit(pos);
+        let selection = if range.start < clicked.start {
+            Selection::new(range.start, Some(clicked.end))
+        } else {
+            Selection::new(range.end.max(clicked.end), Some(clicked.start))
+        };
+        if selection == self.selection() {
+            return false;
+        }
+        self.set_selection(ctx, selection);
+        true
+    }
+
+    /// Counts presses close enough in time and space as one multi-click, cycling through
+    /// single, double and triple clicks.
+    fn count_click(&mut self, (x, y): (i32, i32)) {
+        let now = Instant::now();
+        let (interval, (width, height)) = double_click_settings();
+        self.click_count = match self.last_click {
+            Some((time, (last_x, last_y)))
+                if now - time <= interval
+                    && (x - last_x).abs() <= width
+                    && (y - last_y).abs() <= height =>
+            {
+                self.click_count % 3 + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some((now, (x, y)));
+    }
+
+    /// Word or paragraph at `pos`, depending on the click count.
+    fn click_unit(&mut self, pos: TextPosition) -> Range<TextPosition> {
+        if self.click_count == 2 {
+            self.editor.word_range(pos)
+        } else {
+            self.editor.paragraph_range(pos.paragraph_index)
+        }
+    }
+
+    /// Pixels to scroll per `AUTO_SCROLL_INTERVAL` while dragging above or below the window,
+    /// growing with the distance from its edge.
+    fn auto_scroll_speed(&self) -> i32 {
+        let (_, y) = self.mouse_pos;
+        let distance = if y < 0 {
+            y
+        } else if y >= self.height {
+            y - self.height + 1
+        } else {
+            return 0;
+        };
+        (distance / 4 + distance.signum()).clamp(-self.height, self.height)
+    }
+
+    /// Scrolls to `pos`, clamped to the document.
+    fn set_pos(&mut self, pos: i32) {
+        let max = self.editor.get_height() + self.margin * 2 - self.height;
+        self.pos = pos.min(max).max(0);
+        self.editor.set_viewport(self.pos..self.pos + self.height);
+    }
+
+    /// Shapes the visible text and takes the scroll position corrected for it.
+    fn reshape(&mut self) {
+        self.editor.reshape_all();
+        self.pos = self.editor.viewport().start;
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        // no caret is drawn inside a grapheme cluster, so there is nothing to scroll to.
+        let Some(cursor) = self.editor.get_location(self.text_pos) else {
+            return;
+        };
+        let cursor: IRect = cursor.round_out();
+        // shaping the cursor line may have corrected the heights above the viewport.
+        self.pos = self.editor.viewport().start;
         let temp = cursor.bottom - self.height + self.margin * 2;
         if self.pos < temp {
             self.pos = temp;
         } else if cursor.top < self.pos {
             self.pos = cursor.top;
         }
-        ctx.invalidate();
-        true
+        self.editor.set_viewport(self.pos..self.pos + self.height);
+        if !self.editor.is_wrapped() {
+            let visible = self.width - self.margin * 2;
+            if cursor.right > self.h_pos + visible {
+                self.h_pos = cursor.right - visible;
+            } else if cursor.left < self.h_pos {
+                self.h_pos = cursor.left.max(0);
+            }
+        }
+    }
+}
+
+/// Longest time between the clicks of a double click, and how far apart they may be
+/// horizontally and vertically.
+#[cfg(windows)]
+fn double_click_settings() -> (Duration, (i32, i32)) {
+    #[link(name = "user32")]
+    extern "system" {
+        fn GetDoubleClickTime() -> u32;
+        fn GetSystemMetrics(index: i32) -> i32;
     }
+    const SM_CXDOUBLECLK: i32 = 36;
+    const SM_CYDOUBLECLK: i32 = 37;
+    // SAFETY: all of them only read user settings.
+    let (time, width, height) = unsafe {
+        (
+            GetDoubleClickTime(),
+            GetSystemMetrics(SM_CXDOUBLECLK),
+            GetSystemMetrics(SM_CYDOUBLECLK),
+        )
+    };
+    // the size is that of a rectangle centered on the first click.
+    (Duration::from_millis(time.into()), (width / 2, height / 2))
+}
+
+/// Longest time between the clicks of a double click, and how far apart they may be
+/// horizontally and vertically, read once from GTK's `settings.ini`. macOS only exposes them
+/// through AppKit and GNOME keeps them in gsettings, so the defaults are used there.
+#[cfg(not(windows))]
+fn double_click_settings() -> (Duration, (i32, i32)) {
+    static SETTINGS: OnceLock<(Duration, (i32, i32))> = OnceLock::new();
+    *SETTINGS.get_or_init(|| {
+        // macOS's default interval with a few pixels of tolerance, elsewhere GTK's defaults.
+        let (mut time, mut distance) = if cfg!(target_os = "macos") {
+            (500, 4)
+        } else {
+            (400, 5)
+        };
+        if cfg!(not(target_os = "macos")) {
+            let files = [
+                settings::config_dir().map(|dir| dir.join("gtk-3.0/settings.ini")),
+                Some("/etc/gtk-3.0/settings.ini".into()),
+            ];
+            // the user's file overrides the system's.
+            for text in files
+                .iter()
+                .rev()
+                .flatten()
+                .filter_map(|path| std::fs::read_to_string(path).ok())
+            {
+                for line in text.lines() {
+                    let Some((key, value)) = line.split_once('=') else {
+                        continue;
+                    };
+                    match key.trim() {
+                        "gtk-double-click-time" => time = value.trim().parse().unwrap_or(time),
+                        "gtk-double-click-distance" => {
+                            distance = value.trim().parse().unwrap_or(distance)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        (Duration::from_millis(time), (distance, distance))
+    })
 }
@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use skia::{Font, TypefaceId};
+
+use crate::shape::ShapeResult;
+
+/// Longer paragraphs are rarely repeated and take a lot of memory, so they aren't cached.
+const MAX_TEXT_LEN: usize = 4096;
+
+/// Least recently used shaping results, so that repeated paragraphs like blank lines or a lone
+/// `}`, and paragraphs shaped before a resize or font change that is reverted, are reused.
+pub struct ShapeCache {
+    entries: HashMap<Key, (ShapeResult, u64)>,
+    /// keys by the tick of their last use, oldest first.
+    order: BTreeMap<u64, Key>,
+    capacity: usize,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+struct Key {
+    text: String,
+    font: FontKey,
+    locale: &'static str,
+    /// bits of the `f32` width.
+    width: u32,
+}
+
+/// What `Font`s are compared by, since `Font` itself can't be hashed.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+struct FontKey {
+    typeface: TypefaceId,
+    /// bits of the `f32` size, horizontal scale and skew.
+    size: u32,
+    scale_x: u32,
+    skew_x: u32,
+    edging: i32,
+    hinting: i32,
+    flags: u8,
+}
+
+impl From<&Font> for FontKey {
+    fn from(font: &Font) -> Self {
+        let flags = [
+            font.is_subpixel(),
+            font.is_linear_metrics(),
+            font.is_embolden(),
+            font.is_baseline_snap(),
+            font.is_force_auto_hinting(),
+            font.is_embedded_bitmaps(),
+        ];
+        Self {
+            typeface: font.typeface().unique_id(),
+            size: font.size().to_bits(),
+            scale_x: font.scale_x().to_bits(),
+            skew_x: font.skew_x().to_bits(),
+            edging: font.edging() as i32,
+            hinting: font.hinting() as i32,
+            flags: flags
+                .into_iter()
+                .enumerate()
+                .fold(0, |flags, (i, flag)| flags | u8::from(flag) << i),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lookups = self.hits + self.misses;
+        let rate = if lookups == 0 {
+            0.
+        } else {
+            self.hits as f64 * 100. / lookups as f64
+        };
+        write!(
+            f,
+            "{} hits, {} misses ({rate:.1}% hit rate), {} entries",
+            self.hits, self.misses, self.entries
+        )
+    }
+}
+
+impl ShapeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            capacity,
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        text: &str,
+        font: &Font,
+        locale: &'static str,
+        width: f32,
+    ) -> Option<ShapeResult> {
+        let key = self.key(text, font, locale, width)?;
+        let Some((result, last_use)) = self.entries.get_mut(&key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        let key = self.order.remove(last_use).unwrap();
+        *last_use = self.tick;
+        self.order.insert(self.tick, key);
+        Some(result.clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        text: &str,
+        font: &Font,
+        locale: &'static str,
+        width: f32,
+        result: &ShapeResult,
+    ) {
+        let Some(key) = self.key(text, font, locale, width) else {
+            return;
+        };
+        self.tick += 1;
+        if let Some((_, last_use)) = self
+            .entries
+            .insert(key.clone(), (result.clone(), self.tick))
+        {
+            self.order.remove(&last_use);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > self.capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+
+    fn key(&self, text: &str, font: &Font, locale: &'static str, width: f32) -> Option<Key> {
+        if text.len() > MAX_TEXT_LEN {
+            return None;
+        }
+        Some(Key {
+            text: text.into(),
+            font: font.into(),
+            locale,
+            width: width.to_bits(),
+        })
+    }
+}
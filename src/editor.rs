@@ -1,61 +1,187 @@
-use std::io::BufRead;
+use std::borrow::Cow;
+use std::io::{BufRead, Read, Write};
 use std::ops::Range;
 
+use encoding_rs::Encoding;
+use ropey::{Rope, RopeSlice};
 use skia::{
-    font::Edging, Canvas, Color4f, Contains, Font, FontHinting, FontMgr, FontStyle, IPoint, Paint,
-    Point, Rect, TextBlob,
+    font::Edging, scalar, Canvas, Color4f, Contains, Font, FontHinting, FontMgr, FontStyle, IPoint,
+    Paint, Point, Rect, TextBlob, Typeface,
 };
+use unicode_normalization::char::is_combining_mark;
 
 use crate::{
+    cache::{CacheStats, ShapeCache},
+    error::{Error, Result},
+    file::{self, FileFormat, LineEnding},
+    graphemes,
+    paragraphs::Paragraphs,
     shape::{self, ShapeResult},
+    worker::{Job, ShapeWorker, Shaped},
     UNSET_RECT,
 };
 
+const MAX_FILE_SIZE: u64 = 512 << 20;
+/// pixels above and below the viewport that are shaped ahead of scrolling.
+const SHAPE_MARGIN: i32 = 256;
+const SHAPE_CACHE_CAPACITY: usize = 4096;
+/// families tried in order for the default font: the configured monospace and UI fonts, then
+/// common ones on each platform.
+const FONT_FAMILIES: &[&str] = &[
+    "monospace",
+    "system-ui",
+    "sans-serif",
+    "Cascadia Mono",
+    "Consolas",
+    "Menlo",
+    "DejaVu Sans Mono",
+    "Liberation Mono",
+    "Noto Sans Mono",
+    "Segoe UI",
+    "Helvetica Neue",
+    "Arial",
+];
+/// used when no system font is found, e.g. in a container without fonts.
+const FALLBACK_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
 pub struct Editor {
-    lines: Vec<TextLine>,
+    text: Rope,
+    /// shaping data and height of each paragraph of `text`, the height estimated until the
+    /// paragraph is shaped.
+    lines: Paragraphs<TextLine>,
     width: i32,
-    height: i32,
+    /// whether paragraphs wrap at `width`.
+    wrap: bool,
+    /// right edge of the widest paragraph shaped so far.
+    content_width: i32,
+    /// document y range on screen.
+    viewport: Range<i32>,
     font: Font,
     font_mgr: FontMgr,
     needs_reshape: bool,
+    /// shapes the viewport in the background when set.
+    worker: Option<ShapeWorker>,
+    /// bumped whenever results of the worker can no longer be applied by paragraph index.
+    generation: u64,
+    cache: ShapeCache,
     locale: &'static str,
+    history: History,
+    smart_home: bool,
+    dirty: bool,
+    format: FileFormat,
 }
 
 impl Editor {
     pub fn new() -> Self {
-        // todo: font
-
         let font_mgr = FontMgr::new();
-        let typeface = font_mgr
-            .match_family_style("Arial", FontStyle::default())
-            .unwrap();
-        let mut font = Font::from_typeface(typeface.clone(), 17.0);
+        let typeface = default_typeface(&font_mgr);
+        let mut font = Font::from_typeface(typeface, 17.0);
         font.set_edging(Edging::SubpixelAntiAlias);
         font.set_subpixel(true);
         font.set_hinting(FontHinting::Full);
 
         Editor {
-            lines: Vec::new(),
+            text: Rope::new(),
+            lines: Paragraphs::new([(TextLine::new(), 0)]),
             width: 0,
-            height: 0,
+            wrap: true,
+            content_width: 0,
+            viewport: 0..0,
             font,
             font_mgr,
             needs_reshape: false,
+            worker: None,
+            generation: 0,
+            cache: ShapeCache::new(SHAPE_CACHE_CAPACITY),
             locale: "en",
+            history: Default::default(),
+            smart_home: true,
+            dirty: false,
+            format: Default::default(),
         }
     }
 
     pub fn get_height(&self) -> i32 {
-        self.height
+        self.lines.total()
     }
 
     pub fn set_width(&mut self, w: i32) {
         if self.width != w {
             self.width = w;
+            self.mark_all_dirty();
+        }
+    }
+
+    pub fn is_wrapped(&self) -> bool {
+        self.wrap
+    }
+
+    pub fn set_wrap(&mut self, wrap: bool) {
+        if self.wrap != wrap {
+            self.wrap = wrap;
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Width of the widest paragraph shaped so far, for scrolling horizontally when wrapping is
+    /// off.
+    pub fn content_width(&self) -> i32 {
+        self.content_width
+    }
+
+    pub fn viewport(&self) -> Range<i32> {
+        self.viewport.clone()
+    }
+
+    /// Sets the document y range on screen. Only paragraphs near it are shaped, so its start
+    /// moves when the estimated heights above it get corrected.
+    pub fn set_viewport(&mut self, viewport: Range<i32>) {
+        if self.viewport != viewport {
+            self.viewport = viewport;
             self.needs_reshape = true;
-            for line in &mut self.lines {
-                Self::mark_dirty(line);
-            }
+        }
+    }
+
+    /// When enabled, Home and End first stop at the first and last non-whitespace character of
+    /// the paragraph.
+    pub fn set_smart_home(&mut self, smart_home: bool) {
+        self.smart_home = smart_home;
+    }
+
+    pub fn is_smart_home(&self) -> bool {
+        self.smart_home
+    }
+
+    /// whether the text changed since it was loaded or saved.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+    }
+
+    pub fn format(&self) -> &FileFormat {
+        &self.format
+    }
+
+    /// Converts every line ending to `line_ending` on the next save.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if self.format.line_ending != line_ending || self.format.mixed_line_endings {
+            self.format.line_ending = line_ending;
+            self.format.mixed_line_endings = false;
+            self.lines
+                .for_each_mut(|_, line, _| line.line_ending = None);
+            self.dirty = true;
+        }
+    }
+
+    /// Converts the text to `encoding` on the next save.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if self.format.encoding != encoding {
+            self.format.encoding = encoding;
+            self.format.bom &= file::bom(encoding).is_some();
+            self.dirty = true;
         }
     }
 
@@ -66,227 +192,582 @@ impl Editor {
     pub fn set_font(&mut self, font: Font) {
         if self.font != font {
             self.font = font;
-            self.needs_reshape = true;
-            for line in &mut self.lines {
-                Self::mark_dirty(line);
-            }
+            self.mark_all_dirty();
         }
     }
 
+    /// Shapes the paragraphs around the viewport on `worker` instead of on this thread. Their
+    /// stale text is painted until `apply_shaped` receives the new one.
+    pub fn set_shape_worker(&mut self, worker: ShapeWorker) {
+        self.worker = Some(worker);
+    }
+
+    pub fn shape_cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn font_mgr(&self) -> &FontMgr {
+        &self.font_mgr
+    }
+
     pub fn set_font_mgr(&mut self, font_mgr: FontMgr) {
         self.font_mgr = font_mgr;
-        self.needs_reshape = true;
-        for line in &mut self.lines {
-            Self::mark_dirty(line);
-        }
+        self.mark_all_dirty();
     }
 
-    pub fn text(&self) -> &[TextLine] {
+    pub fn text(&self) -> &Paragraphs<TextLine> {
         &self.lines
     }
 
     pub fn line_height(&self, index: usize) -> i32 {
-        self.lines[index].height
+        self.lines.height(index)
     }
 
-    pub fn mov(&self, mov: Movement, mut pos: TextPosition) -> TextPosition {
-        if self.lines.is_empty() {
-            return TextPosition::new(0, 0);
-        }
-        if pos.paragraph_index >= self.lines.len() {
-            pos.paragraph_index = self.lines.len() - 1;
-            pos.text_byte_index = self.lines.last().unwrap().text.len();
-        } else {
-            let text = &*self.lines[pos.paragraph_index].text;
-            pos.text_byte_index = (0..=pos.text_byte_index)
-                .rev()
-                .find(|&i| text.is_char_boundary(i))
-                .unwrap();
-        }
-
-        assert!(pos.paragraph_index < self.lines.len());
-        assert!(pos.text_byte_index <= self.lines[pos.paragraph_index].text.len());
-        assert!(
-            pos.text_byte_index == self.lines[pos.paragraph_index].text.len()
-                || self.lines[pos.paragraph_index]
-                    .text
-                    .is_char_boundary(pos.text_byte_index)
-        );
+    pub fn mov(&self, mov: Movement, pos: TextPosition) -> TextPosition {
+        let mut pos = self.snap_to_char(pos);
+        let text = self.paragraph_slice(pos.paragraph_index);
+        let len = text.len_bytes();
 
         match mov {
-            Movement::Nowhere => {}
+            Movement::Nowhere => {
+                if !graphemes::is_boundary(text, pos.text_byte_index) {
+                    pos.text_byte_index = graphemes::prev_boundary(text, pos.text_byte_index);
+                }
+            }
             Movement::Left => {
                 if pos.text_byte_index == 0 {
                     if pos.paragraph_index > 0 {
                         pos.paragraph_index -= 1;
-                        pos.text_byte_index = self.lines[pos.paragraph_index].text.len();
+                        pos.text_byte_index = self.paragraph_len(pos.paragraph_index);
                     }
                 } else {
-                    let text = &*self.lines[pos.paragraph_index].text;
-                    pos.text_byte_index = (0..pos.text_byte_index)
-                        .rev()
-                        .find(|&i| text.is_char_boundary(i))
-                        .unwrap();
+                    pos.text_byte_index = graphemes::prev_boundary(text, pos.text_byte_index);
+                }
+            }
+            Movement::CodePointLeft => {
+                let char_index = text.byte_to_char(pos.text_byte_index);
+                match char_index.checked_sub(1).map(|i| text.char(i)) {
+                    Some(c) if is_combining_mark(c) => pos.text_byte_index -= c.len_utf8(),
+                    _ => return self.mov(Movement::Left, pos),
                 }
             }
-            Movement::Up => todo!(),
+            Movement::Up | Movement::Down => {
+                let x = self.cursor_x(pos);
+                pos = self.mov_to_x(mov, pos, x);
+            }
+            Movement::VisualLeft | Movement::VisualRight => {
+                let x = self.cursor_x(pos);
+                (pos, _) = self.mov_to_side(mov, pos, x);
+            }
             Movement::Right => {
-                let text = &*self.lines[pos.paragraph_index].text;
-                if let Some(i) = (1..=4)
-                    .map(|i| pos.text_byte_index + i)
-                    .find(|&i| text.is_char_boundary(i))
-                {
-                    pos.text_byte_index = i;
+                if pos.text_byte_index < len {
+                    pos.text_byte_index = graphemes::next_boundary(text, pos.text_byte_index);
                 } else if pos.paragraph_index + 1 < self.lines.len() {
                     pos.paragraph_index += 1;
                     pos.text_byte_index = 0;
                 }
             }
-            Movement::Down => todo!(),
-            Movement::Home => todo!(),
-            Movement::End => todo!(),
-            Movement::WordLeft => todo!(),
-            Movement::WordRight => todo!(),
+            Movement::Home => {
+                let line = &self.lines[pos.paragraph_index];
+                let Range { start, end } = line.visual_line(pos.text_byte_index, len);
+                let indent = start + whitespace_len(text.byte_slice(start..end).chars());
+                let smart = Some(indent).filter(|_| self.smart_home && start == 0);
+                pos.text_byte_index = [smart, Some(start), Some(0)]
+                    .into_iter()
+                    .flatten()
+                    .find(|&i| i < pos.text_byte_index)
+                    .or(smart)
+                    .unwrap_or(pos.text_byte_index);
+            }
+            Movement::End => {
+                let line = &self.lines[pos.paragraph_index];
+                let Range { start, end } = line.visual_line(pos.text_byte_index, len);
+                let end = if end < len {
+                    // soft wrap: stay before the grapheme cluster that ends this visual line, as
+                    // a caret at `end` is drawn at the start of the next one.
+                    graphemes::prev_boundary(text, end)
+                } else {
+                    end
+                };
+                let visual_text = text.byte_slice(start..end);
+                let trailing = visual_text.chars_at(visual_text.len_chars()).reversed();
+                let trimmed = end - whitespace_len(trailing);
+                let smart = Some(trimmed).filter(|_| self.smart_home && end == len);
+                pos.text_byte_index = [smart, Some(end), Some(len)]
+                    .into_iter()
+                    .flatten()
+                    .find(|&i| i > pos.text_byte_index)
+                    .or(smart)
+                    .unwrap_or(pos.text_byte_index);
+            }
+            Movement::DocumentStart => pos = TextPosition::new(0, 0),
+            Movement::DocumentEnd => {
+                pos.paragraph_index = self.lines.len() - 1;
+                pos.text_byte_index = self.paragraph_len(pos.paragraph_index);
+            }
+            Movement::WordLeft => {
+                if pos.text_byte_index == 0 {
+                    return self.mov(Movement::Left, pos);
+                }
+                let line = &self.lines[pos.paragraph_index];
+                // skip whitespace-only segments, stop at the start of a word.
+                loop {
+                    let prev = line.prev_word_boundary(pos.text_byte_index, len);
+                    let skipped = text.byte_slice(prev..pos.text_byte_index);
+                    pos.text_byte_index = prev;
+                    if prev == 0 || !skipped.chars().all(char::is_whitespace) {
+                        break;
+                    }
+                }
+            }
+            Movement::WordRight => {
+                let line = &self.lines[pos.paragraph_index];
+                if pos.text_byte_index == len {
+                    return self.mov(Movement::Right, pos);
+                }
+                // skip whitespace-only segments, stop at the end of a word.
+                loop {
+                    let next = line.next_word_boundary(pos.text_byte_index, len);
+                    let skipped = text.byte_slice(pos.text_byte_index..next);
+                    pos.text_byte_index = next;
+                    if next == len || !skipped.chars().all(char::is_whitespace) {
+                        break;
+                    }
+                }
+            }
         }
         return pos;
     }
 
-    pub fn get_position(&mut self, xy: IPoint) -> Option<TextPosition> {
-        self.reshape_all();
-        let mut approximate_position = None;
-        for (j, line) in self.lines.iter().enumerate() {
-            let mut line_rect = Rect::new(
-                0.,
-                line.origin.y as _,
-                self.width as _,
-                if let Some(l) = self.lines.get(j + 1) {
-                    l.origin.y as _
-                } else {
-                    f32::MAX
+    /// Moves `pos` into the text and back to the start of the char it is in, which may be inside
+    /// a grapheme cluster.
+    fn snap_to_char(&self, mut pos: TextPosition) -> TextPosition {
+        if pos.paragraph_index >= self.lines.len() {
+            pos.paragraph_index = self.lines.len() - 1;
+            pos.text_byte_index = usize::MAX;
+        }
+        let text = self.paragraph_slice(pos.paragraph_index);
+        pos.text_byte_index = graphemes::floor_char_boundary(text, pos.text_byte_index);
+        pos
+    }
+
+    /// Moves `pos` to the end of the grapheme cluster it is in, unless it is at a boundary.
+    fn snap_forward(&self, mut pos: TextPosition) -> TextPosition {
+        let text = self.paragraph_slice(pos.paragraph_index);
+        if !graphemes::is_boundary(text, pos.text_byte_index) {
+            pos.text_byte_index = graphemes::next_boundary(text, pos.text_byte_index);
+        }
+        pos
+    }
+
+    /// Moves `pos` one visual line up or down, staying as close as possible to `x`, which
+    /// defaults to the caret's own x. Returns the new position and the x to keep for the next
+    /// vertical move.
+    pub fn mov_vertical(
+        &mut self,
+        mov: Movement,
+        pos: TextPosition,
+        x: Option<scalar>,
+    ) -> (TextPosition, scalar) {
+        let pos = self.mov(Movement::Nowhere, pos);
+        self.shape_paragraph(pos.paragraph_index);
+        match mov {
+            Movement::Up if pos.paragraph_index > 0 => {
+                self.shape_paragraph(pos.paragraph_index - 1)
+            }
+            Movement::Down => self.shape_paragraph(pos.paragraph_index + 1),
+            _ => {}
+        }
+        let x = x.unwrap_or_else(|| self.cursor_x(pos));
+        (self.mov_to_x(mov, pos, x), x)
+    }
+
+    /// Moves `pos` past the grapheme cluster left or right of the caret at `x`, which defaults to
+    /// the caret's own x. Where the direction of the text changes, a position has two carets, so
+    /// the x of the one that moved is returned to be passed to the next visual move.
+    pub fn mov_visual(
+        &mut self,
+        mov: Movement,
+        pos: TextPosition,
+        x: Option<scalar>,
+    ) -> (TextPosition, scalar) {
+        let pos = self.mov(Movement::Nowhere, pos);
+        self.shape_paragraph(pos.paragraph_index);
+        let x = x.unwrap_or_else(|| self.cursor_x(pos));
+        let (pos, x) = self.mov_to_side(mov, pos, x);
+        self.shape_paragraph(pos.paragraph_index);
+        (pos, x.unwrap_or_else(|| self.cursor_x(pos)))
+    }
+
+    /// Whether paragraph `index` is right-to-left, from its first strong character.
+    pub fn is_rtl(&self, index: usize) -> bool {
+        index < self.lines.len()
+            && shape::paragraph_level(self.paragraph_slice(index).chars()) % 2 == 1
+    }
+
+    fn cursor_x(&self, pos: TextPosition) -> scalar {
+        self.lines
+            .get(pos.paragraph_index)
+            .and_then(|line| line.caret_x(pos.text_byte_index))
+            .unwrap_or(0.)
+    }
+
+    /// Moves past the nearest grapheme cluster of the visual line on the side of `mov`, or to the
+    /// neighbouring line in reading order at its edge. Returns the new x of the caret, unless
+    /// the move left the line.
+    fn mov_to_side(
+        &self,
+        mov: Movement,
+        pos: TextPosition,
+        x: scalar,
+    ) -> (TextPosition, Option<scalar>) {
+        let right = mov == Movement::VisualRight;
+        let text = self.paragraph_slice(pos.paragraph_index);
+        let len = text.len_bytes();
+        let line = &self.lines[pos.paragraph_index];
+        let Range { start, end } = line.visual_line(pos.text_byte_index, len);
+        let next = graphemes::clusters(text, start..end)
+            .filter_map(
+                |Range {
+                     start: i,
+                     end: next,
+                 }| {
+                    let rect = line.cursor_pos.get(i)?;
+                    let (near, far) = if right {
+                        (rect.left, rect.right)
+                    } else {
+                        (rect.right, rect.left)
+                    };
+                    let ahead = if right {
+                        near >= x - 0.5
+                    } else {
+                        near <= x + 0.5
+                    };
+                    (*rect != UNSET_RECT && ahead).then_some((i, next - i, near, far))
                 },
-            );
-            if let Some(blob) = &line.blob {
-                line_rect.join(blob.bounds())
+            )
+            .min_by(|a, b| (a.2 - x).abs().total_cmp(&(b.2 - x).abs()));
+        if let Some((i, len, _, far)) = next {
+            // the caret is after a left-to-right cluster that it passed, before a right-to-left one.
+            let index = if line.is_rtl(i) == right { i } else { i + len };
+            return (TextPosition::new(index, pos.paragraph_index), Some(far));
+        }
+        let forward = right != self.is_rtl(pos.paragraph_index);
+        let pos = match forward {
+            true if end < len => TextPosition::new(end, pos.paragraph_index),
+            true => self.mov(Movement::Right, TextPosition::new(end, pos.paragraph_index)),
+            false => self.mov(
+                Movement::Left,
+                TextPosition::new(start, pos.paragraph_index),
+            ),
+        };
+        (pos, None)
+    }
+
+    fn mov_to_x(&self, mov: Movement, mut pos: TextPosition, x: scalar) -> TextPosition {
+        let line = &self.lines[pos.paragraph_index];
+        let list = &line.line_end_offsets;
+        // visual line containing pos: list[f - 1] <= pos < list[f]
+        let f = list.partition_point(|&offset| offset <= pos.text_byte_index);
+        match mov {
+            Movement::Up if f > 0 => {
+                // not the first line in paragraph.
+                let begin = if f == 1 { 0 } else { list[f - 2] };
+                pos.text_byte_index = find_closest_x(line, x, begin..list[f - 1]);
             }
-            if !line_rect.contains(Point::from(xy)) {
-                continue;
+            Movement::Up if pos.paragraph_index > 0 => {
+                pos.paragraph_index -= 1;
+                let line = &self.lines[pos.paragraph_index];
+                let begin = line.line_end_offsets.last().copied().unwrap_or(0);
+                pos.text_byte_index = find_closest_x(line, x, begin..line.cursor_pos.len());
             }
-            let pt = Point::from(xy - line.origin);
-            for (i, pos) in line.cursor_pos.iter().enumerate() {
-                if pos != &UNSET_RECT && pos.contains(pt) {
-                    return Some(TextPosition::new(i, j));
-                }
+            Movement::Up => pos.text_byte_index = 0,
+            Movement::Down if f < list.len() => {
+                let end = list.get(f + 1).copied().unwrap_or(line.cursor_pos.len());
+                pos.text_byte_index = find_closest_x(line, x, list[f]..end);
             }
-            approximate_position = Some(TextPosition::new(
-                if xy.x <= line.origin.x {
-                    0
-                } else {
-                    line.text.len()
-                },
-                j,
-            ));
+            Movement::Down if pos.paragraph_index + 1 < self.lines.len() => {
+                pos.paragraph_index += 1;
+                let line = &self.lines[pos.paragraph_index];
+                let end = line
+                    .line_end_offsets
+                    .first()
+                    .copied()
+                    .unwrap_or(line.cursor_pos.len());
+                pos.text_byte_index = find_closest_x(line, x, 0..end);
+            }
+            Movement::Down => pos.text_byte_index = self.paragraph_len(pos.paragraph_index),
+            _ => unreachable!(),
         }
-        approximate_position
+        self.mov(Movement::Nowhere, pos)
     }
 
-    pub fn get_location(&mut self, cursor: TextPosition) -> Option<Rect> {
+    pub fn get_position(&mut self, xy: IPoint) -> Option<TextPosition> {
         self.reshape_all();
-        if self.lines.is_empty() {
+        if xy.y < 0 {
             return None;
         }
+        let j = self.lines.find(xy.y);
+        self.shape_paragraph(j);
+        let origin = self.origin(j);
+        let pt = Point::from(xy - origin);
+        for (i, pos) in self.lines[j].cursor_pos.iter().enumerate() {
+            if pos != &UNSET_RECT && pos.contains(pt) {
+                return Some(TextPosition::new(i, j));
+            }
+        }
+        // a right-to-left paragraph starts at the right.
+        Some(TextPosition::new(
+            if (xy.x <= origin.x) != self.is_rtl(j) {
+                0
+            } else {
+                self.paragraph_len(j)
+            },
+            j,
+        ))
+    }
+
+    /// Range of the word, or of the run of spaces or punctuation, at `pos`, following the word
+    /// boundaries found by shaping.
+    pub fn word_range(&mut self, pos: TextPosition) -> Range<TextPosition> {
+        let pos = self.mov(Movement::Nowhere, pos);
+        self.shape_paragraph(pos.paragraph_index);
+        let len = self.paragraph_len(pos.paragraph_index);
+        let line = &self.lines[pos.paragraph_index];
+        let index = pos.text_byte_index;
+        let (start, end) = if index == len {
+            (line.prev_word_boundary(index, len), len)
+        } else if line.is_word_boundary(index, len) {
+            (index, line.next_word_boundary(index, len))
+        } else {
+            (
+                line.prev_word_boundary(index, len),
+                line.next_word_boundary(index, len),
+            )
+        };
+        TextPosition::new(start, pos.paragraph_index)..TextPosition::new(end, pos.paragraph_index)
+    }
+
+    /// Range of paragraph `index`, including its newline.
+    pub fn paragraph_range(&self, index: usize) -> Range<TextPosition> {
+        let index = index.min(self.lines.len() - 1);
+        let start = TextPosition::new(0, index);
+        if index + 1 < self.lines.len() {
+            start..TextPosition::new(0, index + 1)
+        } else {
+            start..TextPosition::new(self.paragraph_len(index), index)
+        }
+    }
+
+    pub fn get_location(&mut self, cursor: TextPosition) -> Option<Rect> {
         let cursor = self.mov(Movement::Nowhere, cursor);
+        self.shape_paragraph(cursor.paragraph_index);
         let line = &self.lines[cursor.paragraph_index];
-        let mut pos = match line.cursor_pos.get(cursor.text_byte_index) {
-            None => return None,
-            Some(&pos) => pos,
+        let rect = line.cursor_pos.get(cursor.text_byte_index)?;
+        let x = line.caret_x(cursor.text_byte_index)?;
+        let caret = Rect::new(x - 1., rect.top, x + 1., rect.bottom);
+        Some(caret.with_offset(self.origin(cursor.paragraph_index)))
+    }
+
+    /// Second caret of a position where the direction of the text changes, after the grapheme
+    /// cluster before it. `get_location` gives the one before the cluster after it.
+    pub fn get_split_location(&mut self, cursor: TextPosition) -> Option<Rect> {
+        let cursor = self.mov(Movement::Nowhere, cursor);
+        if cursor.text_byte_index == 0 {
+            return None;
+        }
+        let index = cursor.text_byte_index;
+        let prev = self.mov(Movement::Left, cursor).text_byte_index;
+        let len = self.paragraph_len(cursor.paragraph_index);
+        let line = &self.lines[cursor.paragraph_index];
+        // the two sides of a soft wrap are on different lines.
+        if line.is_rtl(prev) == line.is_rtl(index)
+            || line.visual_line(prev, len) != line.visual_line(index, len)
+        {
+            return None;
+        }
+        let prev_rect = line.cursor_pos.get(prev)?;
+        let x = if line.is_rtl(prev) {
+            prev_rect.left
+        } else {
+            prev_rect.right
         };
-        pos.right = pos.left + 1.;
-        pos.left -= 1.;
-        Some(pos.with_offset(line.origin))
+        let caret = Rect::new(x - 1., prev_rect.top, x + 1., prev_rect.bottom);
+        Some(caret.with_offset(self.origin(cursor.paragraph_index)))
     }
 
     pub fn insert(&mut self, pos: TextPosition, text: &str) -> TextPosition {
         if text.is_empty() {
             return pos;
         }
+        let pos = self.snap_to_char(pos);
+        let end = self.insert_untracked(pos, text);
+        // text typed before a combining mark takes the mark; the caret goes after both.
+        let end = self.snap_forward(end);
+        self.history
+            .record(Edit::Insert(pos, text.into()), pos, end);
+        end
+    }
 
-        fn to_text_line(text: &str) -> TextLine {
-            TextLine::new(text.into())
+    pub fn remove(&mut self, range: Range<TextPosition>) -> TextPosition {
+        // `Movement::CodePointLeft` removes a part of a grapheme cluster, and undo puts it back.
+        let start = self.snap_to_char(range.start);
+        let end = self.snap_to_char(range.end);
+        let removed = self.text_in(start..end);
+        if !removed.is_empty() {
+            self.history
+                .record(Edit::Remove(start, removed), end, start);
         }
+        let start = self.remove_untracked(start..end);
+        self.snap_forward(start)
+    }
 
-        self.needs_reshape = true;
-        let pos = self.mov(Movement::Nowhere, pos);
+    /// Starts grouping the following edits into one undo step.
+    pub fn begin_transaction(&mut self, kind: EditKind, selection: Selection) {
+        self.history.begin(kind, selection);
+    }
 
-        let mut parts = text.split('\n');
-        if self.lines.len() <= pos.paragraph_index {
-            assert_eq!(pos.paragraph_index, self.lines.len());
-            assert_eq!(pos.text_byte_index, 0);
-            self.lines.extend(parts.map(to_text_line));
-            return TextPosition::new(self.lines.last().unwrap().text.len(), self.lines.len());
-        };
+    /// Closes the group opened by `begin_transaction`, merging it into the previous one if it
+    /// continues the same kind of edit.
+    pub fn end_transaction(&mut self, selection: Selection) {
+        self.history.end(selection);
+    }
 
+    /// Reverts the last undo step and returns the selection from before it.
+    pub fn undo(&mut self) -> Option<Selection> {
+        self.history.close();
+        let transaction = self.history.undo_stack.pop()?;
+        for edit in transaction.edits.iter().rev() {
+            match edit {
+                Edit::Insert(pos, text) => {
+                    self.remove_untracked(*pos..pos.advance(text));
+                }
+                Edit::Remove(pos, text) => {
+                    self.insert_untracked(*pos, text);
+                }
+            }
+        }
+        let selection = transaction.before;
+        self.history.redo_stack.push(transaction);
+        Some(selection)
+    }
+
+    /// Re-applies the last undone step and returns the selection from after it.
+    pub fn redo(&mut self) -> Option<Selection> {
+        self.history.close();
+        let transaction = self.history.redo_stack.pop()?;
+        for edit in &transaction.edits {
+            match edit {
+                Edit::Insert(pos, text) => {
+                    self.insert_untracked(*pos, text);
+                }
+                Edit::Remove(pos, text) => {
+                    self.remove_untracked(*pos..pos.advance(text));
+                }
+            }
+        }
+        let selection = transaction.after;
+        self.history.undo_stack.push(transaction);
+        Some(selection)
+    }
+
+    fn insert_untracked(&mut self, pos: TextPosition, text: &str) -> TextPosition {
+        if text.is_empty() {
+            return pos;
+        }
+        self.needs_reshape = true;
+        self.dirty = true;
+        let pos = self.snap_to_char(pos);
+        self.text.insert(self.char_index(pos), text);
         Self::mark_dirty(&mut self.lines[pos.paragraph_index]);
-        let first = parts.next().unwrap();
-        if let Some(next) = parts.next() {
-            let len = self.lines.len();
-            let next_index = pos.paragraph_index + 1;
-            self.lines.splice(
-                next_index..next_index,
-                [to_text_line(next)]
-                    .into_iter()
-                    .chain(parts.map(to_text_line)),
-            );
-            let last_index = pos.paragraph_index + self.lines.len() - len;
-            let [head, .., foot] = &mut self.lines[pos.paragraph_index..=last_index] else {
-                unreachable!()
-            };
-            let res = TextPosition::new(foot.text.len(), last_index);
-            foot.text
-                .push_str(head.text.drain(pos.text_byte_index..).as_str());
-            head.text.push_str(first);
-            res
-        } else {
-            // fast path
-            self.lines[pos.paragraph_index]
-                .text
-                .insert_str(pos.text_byte_index, first);
-            TextPosition::new(pos.text_byte_index + first.len(), pos.paragraph_index)
+        let end = pos.advance(text);
+        if end.paragraph_index == pos.paragraph_index {
+            // the height of the edited paragraph is set once it is shaped again.
+            return end;
         }
+        self.generation += 1;
+        let heights: Vec<_> = (pos.paragraph_index + 1..=end.paragraph_index)
+            .map(self.estimator())
+            .collect();
+        let next_index = pos.paragraph_index + 1;
+        self.lines.splice(
+            next_index..next_index,
+            heights.into_iter().map(|height| (TextLine::new(), height)),
+        );
+        // the paragraph's line ending now ends the last inserted one.
+        let line_ending = self.lines[pos.paragraph_index].line_ending.take();
+        self.lines[end.paragraph_index].line_ending = line_ending;
+        end
     }
 
-    pub fn remove(&mut self, range: Range<TextPosition>) -> TextPosition {
+    fn remove_untracked(&mut self, range: Range<TextPosition>) -> TextPosition {
         let Range { start, end } = range;
         if start == end || start.paragraph_index >= self.lines.len() {
             return start;
         }
         self.needs_reshape = true;
-        if let [head, .., foot] = &mut self.lines[start.paragraph_index..=end.paragraph_index] {
-            Self::mark_dirty(head);
-            head.text
-                .replace_range(start.text_byte_index.., &foot.text[..=end.text_byte_index]);
-            drop(
-                self.lines
-                    .drain(start.paragraph_index + 1..end.paragraph_index),
-            );
-        } else {
-            let line = &mut self.lines[start.paragraph_index];
-            Self::mark_dirty(line);
-            line.text
-                .replace_range(start.text_byte_index..end.text_byte_index, "");
+        self.dirty = true;
+        self.text
+            .remove(self.char_index(start)..self.char_index(end));
+        Self::mark_dirty(&mut self.lines[start.paragraph_index]);
+        if end.paragraph_index == start.paragraph_index {
+            return start;
         }
+        self.generation += 1;
+        self.lines[start.paragraph_index].line_ending = self.lines[end.paragraph_index].line_ending;
+        let removed = start.paragraph_index + 1..end.paragraph_index + 1;
+        self.lines.splice(removed, []);
         start
     }
 
+    fn text_in(&self, range: Range<TextPosition>) -> String {
+        let Range { start, end } = range;
+        if start >= end {
+            return String::new();
+        }
+        self.text
+            .slice(self.char_index(start)..self.char_index(end))
+            .to_string()
+    }
+
+    /// index into `text` of the char at `pos`.
+    fn char_index(&self, pos: TextPosition) -> usize {
+        self.text
+            .byte_to_char(self.text.line_to_byte(pos.paragraph_index) + pos.text_byte_index)
+    }
+
+    fn paragraph(&self, index: usize) -> Cow<'_, str> {
+        paragraph(&self.text, index)
+    }
+
+    fn paragraph_slice(&self, index: usize) -> RopeSlice<'_> {
+        paragraph_slice(&self.text, index)
+    }
+
+    fn paragraph_len(&self, index: usize) -> usize {
+        let line = self.text.line(index);
+        line.len_bytes() - usize::from(index + 1 < self.text.len_lines())
+    }
+
+    /// Copies the UTF-8 text in `range` into `dst`, joining paragraphs with `\n`.
+    /// Returns the byte length of the whole range, which may exceed `dst`.
     pub fn copy(&self, range: Range<TextPosition>, dst: Option<&mut [u8]>) -> usize {
-        todo!()
+        let start = self.mov(Movement::Nowhere, range.start);
+        let end = self.mov(Movement::Nowhere, range.end);
+        let text = self.text_in(start..end);
+        if let Some(dst) = dst {
+            let len = text.len().min(dst.len());
+            dst[..len].copy_from_slice(&text.as_bytes()[..len]);
+        }
+        text.len()
     }
 
     pub fn line_count(&self) -> usize {
         self.lines.len()
     }
 
-    pub fn line(&self, i: usize) -> Option<StringView> {
-        self.lines.get(i).map(|line| &*line.text)
+    pub fn line(&self, i: usize) -> Option<StringView<'_>> {
+        (i < self.lines.len()).then(|| self.paragraph(i))
     }
 
     pub fn paint(&mut self, canvas: &Canvas, options: PaintOpts) {
@@ -294,87 +775,390 @@ impl Editor {
 
         canvas.draw_paint(&Paint::new(options.background_color, None));
 
-        if self.lines.is_empty() {
-            return;
-        }
+        // the shaped paragraphs around the viewport.
+        let visible = self.lines.find(self.viewport.start - SHAPE_MARGIN)
+            ..self.lines.find(self.viewport.end + SHAPE_MARGIN) + 1;
 
         if let Some((mark, current)) = options.selection {
             let selection = Paint::new(options.selection_color, None);
-            let Range {
-                start: mut pos,
-                end,
-            } = TextPosition::range(mark, current);
+            let Range { start, end } = TextPosition::range(mark, current);
+            let mut pos = start.max(TextPosition::new(0, visible.start));
+            let end = end.min(TextPosition::new(0, visible.end));
+            // each grapheme cluster is painted on its own, so a range across runs of both
+            // directions is covered even where its parts aren't next to each other on screen.
             while pos < end {
+                // the bounds may be stale or missing while the worker shapes the line.
                 let line = &self.lines[pos.paragraph_index];
-                canvas.draw_rect(
-                    line.cursor_pos[pos.text_byte_index].with_offset(line.origin),
-                    &selection,
-                );
+                if let Some(rect) = line.cursor_pos.get(pos.text_byte_index) {
+                    canvas.draw_rect(
+                        rect.with_offset(self.origin(pos.paragraph_index)),
+                        &selection,
+                    );
+                }
                 pos = self.mov(Movement::Right, pos);
             }
         }
 
         if let Some(cursor) = options.cursor {
+            let paint = Paint::new(options.cursor_color, None);
             if let Some(rect) = self.get_location(cursor) {
-                canvas.draw_rect(rect, &Paint::new(options.cursor_color, None));
+                match self.get_split_location(cursor) {
+                    // split caret: the upper half before the next cluster, the lower half after
+                    // the previous one.
+                    Some(split) => {
+                        canvas.draw_rect(
+                            Rect {
+                                bottom: rect.center_y(),
+                                ..rect
+                            },
+                            &paint,
+                        );
+                        canvas.draw_rect(
+                            Rect {
+                                top: split.center_y(),
+                                ..split
+                            },
+                            &paint,
+                        );
+                    }
+                    None => {
+                        canvas.draw_rect(rect, &paint);
+                    }
+                }
             }
         }
 
         let foreground = Paint::new(options.foreground_color, None);
-        for line in &self.lines {
-            if let Some(blob) = &line.blob {
-                canvas.draw_text_blob(blob, line.origin, &foreground);
+        let mut y = self.lines.offset(visible.start);
+        for i in visible {
+            if let Some(blob) = &self.lines[i].blob {
+                canvas.draw_text_blob(blob, IPoint::new(0, y), &foreground);
             }
+            y += self.lines.height(i);
         }
     }
 
+    /// Keeps the blob, which is painted until the line is shaped again.
     fn mark_dirty(line: &mut TextLine) {
-        line.blob = None;
         line.shaped = false;
+        line.revision += 1;
+        line.requested = None;
+        line.line_end_offsets = vec![];
         line.word_boundaries = vec![];
     }
 
-    fn reshape_all(&mut self) {
+    /// Marks every paragraph for reshaping, keeping the one at the top of the viewport there.
+    fn mark_all_dirty(&mut self) {
+        let (anchor, _) = self.anchor();
+        let heights: Vec<_> = (0..self.lines.len()).map(self.estimator()).collect();
+        self.lines.for_each_mut(|i, line, height| {
+            Self::mark_dirty(line);
+            *height = heights[i];
+        });
+        self.scroll_to_anchor(anchor, 0);
+        self.content_width = 0;
+        self.generation += 1;
+        self.needs_reshape = true;
+    }
+
+    /// Height of a paragraph guessed from its length, used until it is shaped.
+    fn estimator(&self) -> impl Fn(usize) -> i32 + '_ {
+        let spacing = self.font.spacing();
+        let (advance, _) = self.font.measure_str("0", None);
+        let width = self.width.max(1) as scalar;
+        move |index| {
+            if !self.wrap {
+                return spacing.ceil() as _;
+            }
+            let chars = self.text.line(index).len_chars();
+            let lines = (chars as scalar * advance / width).ceil().max(1.);
+            (lines * spacing).ceil() as _
+        }
+    }
+
+    /// Shapes the paragraphs in and near the viewport. The others keep estimated heights.
+    pub fn reshape_all(&mut self) {
         if !self.needs_reshape {
             return;
         }
-        if self.lines.is_empty() {
-            self.lines.push(TextLine::new(String::new()));
-        }
-        let shape_width = self.width as _;
-        for line in self.lines.iter_mut().filter(|line| !line.shaped) {
-            let ShapeResult {
-                blob,
-                line_break_offsets,
-                glyph_bounds,
-                word_breaks,
-                vertical_advance,
-            } = shape::shape(
-                &line.text,
-                &self.font,
-                self.font_mgr.clone(),
-                self.locale,
-                shape_width,
-            );
-            line.blob = blob;
-            line.cursor_pos = glyph_bounds;
-            line.line_end_offsets = line_break_offsets;
-            line.word_boundaries = word_breaks;
-            line.height = vertical_advance;
-            line.shaped = true;
-        }
-        self.height = self.lines.iter_mut().fold(0, |y, line| {
-            line.origin = IPoint::new(0, y);
-            y + line.height
-        });
+        let (anchor, offset) = self.anchor();
+        let Range { start, end } = self.viewport;
+        let mut requests = vec![];
+        let mut y = start - offset;
+        for i in anchor..self.lines.len() {
+            if y >= end + SHAPE_MARGIN {
+                break;
+            }
+            self.shape_or_request(i, &mut requests);
+            y += self.lines.height(i);
+        }
+        let mut y = start - offset;
+        for i in (0..anchor).rev() {
+            if y <= start - SHAPE_MARGIN {
+                break;
+            }
+            self.shape_or_request(i, &mut requests);
+            y -= self.lines.height(i);
+        }
+        self.scroll_to_anchor(anchor, offset);
         self.needs_reshape = false;
+        if let (Some(worker), false) = (&self.worker, requests.is_empty()) {
+            worker.send(Job {
+                generation: self.generation,
+                text: self.text.clone(),
+                paragraphs: requests,
+                font: self.font.clone(),
+                locale: self.locale,
+                width: self.shape_width(),
+            });
+        }
     }
 
-    pub fn load(&mut self, reader: impl BufRead) {
-        self.lines
-            .splice(.., reader.lines().map(|s| TextLine::new(s.unwrap())));
+    /// Shapes paragraph `index` now, or adds it to `requests` for the worker unless it is
+    /// cached.
+    fn shape_or_request(&mut self, index: usize, requests: &mut Vec<(usize, u64)>) {
+        let line = &self.lines[index];
+        if line.shaped || line.requested == Some(self.generation) {
+            return;
+        }
+        if self.worker.is_none() {
+            return self.shape_line(index);
+        }
+        let width = self.shape_width();
+        let cached = self.cache.get(
+            &paragraph(&self.text, index),
+            &self.font,
+            self.locale,
+            width,
+        );
+        match cached {
+            Some(result) => self.set_shape_result(index, result),
+            None => {
+                let line = &mut self.lines[index];
+                line.requested = Some(self.generation);
+                requests.push((index, line.revision));
+            }
+        }
+    }
+
+    /// Takes a paragraph shaped by the worker, unless it changed in the meantime. Returns whether
+    /// it needs to be painted.
+    pub fn apply_shaped(&mut self, shaped: Shaped) -> bool {
+        let index = shaped.paragraph_index;
+        match self.lines.get(index) {
+            Some(line)
+                if shaped.generation == self.generation
+                    && line.revision == shaped.revision
+                    && !line.shaped => {}
+            _ => return false,
+        }
+        self.cache.insert(
+            &paragraph(&self.text, index),
+            &self.font,
+            self.locale,
+            self.shape_width(),
+            &shaped.result,
+        );
+        let (anchor, offset) = self.anchor();
+        self.set_shape_result(index, shaped.result);
+        self.scroll_to_anchor(anchor, offset);
+        // the corrected height may bring more paragraphs into view.
         self.needs_reshape = true;
+        true
+    }
+
+    /// Shapes paragraph `index` even when it is far from the viewport.
+    pub fn shape_paragraph(&mut self, index: usize) {
+        self.reshape_all();
+        if index < self.lines.len() && !self.lines[index].shaped {
+            let (anchor, offset) = self.anchor();
+            self.shape_line(index);
+            self.scroll_to_anchor(anchor, offset);
+        }
+    }
+
+    /// Shapes the paragraphs within `height` pixels above paragraph `index`, so that its y no
+    /// longer depends on estimates below that.
+    pub fn shape_paragraphs_before(&mut self, index: usize, height: i32) {
+        self.reshape_all();
+        let (anchor, offset) = self.anchor();
+        let mut y = 0;
+        for i in (0..index.min(self.lines.len())).rev() {
+            if y >= height {
+                break;
+            }
+            self.shape_line(i);
+            y += self.lines.height(i);
+        }
+        self.scroll_to_anchor(anchor, offset);
+    }
+
+    fn shape_line(&mut self, index: usize) {
+        if self.lines[index].shaped {
+            return;
+        }
+        let text = paragraph(&self.text, index);
+        let width = self.shape_width();
+        let result = match self.cache.get(&text, &self.font, self.locale, width) {
+            Some(result) => result,
+            None => {
+                let result =
+                    shape::shape(&text, &self.font, self.font_mgr.clone(), self.locale, width);
+                self.cache
+                    .insert(&text, &self.font, self.locale, width, &result);
+                result
+            }
+        };
+        drop(text);
+        self.set_shape_result(index, result);
+    }
+
+    fn shape_width(&self) -> scalar {
+        if self.wrap {
+            self.width as _
+        } else {
+            scalar::MAX
+        }
     }
+
+    fn set_shape_result(&mut self, index: usize, result: ShapeResult) {
+        let ShapeResult {
+            blob,
+            line_break_offsets,
+            glyph_bounds,
+            word_breaks,
+            rtl,
+            vertical_advance,
+        } = result;
+        // the end of a right-to-left paragraph is at the left, so look at every cluster.
+        let right = glyph_bounds
+            .iter()
+            .map(|rect| rect.right)
+            .fold(0., scalar::max);
+        self.content_width = self.content_width.max(right.ceil() as _);
+        let line = &mut self.lines[index];
+        line.blob = blob;
+        line.cursor_pos = glyph_bounds;
+        line.line_end_offsets = line_break_offsets;
+        line.word_boundaries = word_breaks;
+        line.rtl = rtl;
+        line.shaped = true;
+        self.lines.set_height(index, vertical_advance);
+    }
+
+    /// paragraph at the top of the viewport and how far the viewport starts into it.
+    fn anchor(&self) -> (usize, i32) {
+        let index = self.lines.find(self.viewport.start);
+        (index, self.viewport.start - self.lines.offset(index))
+    }
+
+    /// Moves the viewport so that it starts `offset` pixels into paragraph `anchor`.
+    fn scroll_to_anchor(&mut self, anchor: usize, offset: i32) {
+        let top = self.lines.offset(anchor) + offset;
+        self.viewport = top..top + self.viewport.len() as i32;
+    }
+
+    fn origin(&self, index: usize) -> IPoint {
+        IPoint::new(0, self.lines.offset(index))
+    }
+
+    /// Loads the text, decoding it with `encoding` or with the detected encoding.
+    pub fn load(
+        &mut self,
+        reader: impl BufRead,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<()> {
+        let mut bytes = vec![];
+        reader.take(MAX_FILE_SIZE + 1).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 > MAX_FILE_SIZE {
+            return Err(Error::FileTooLarge {
+                limit: MAX_FILE_SIZE,
+            });
+        }
+        let (text, encoding, bom, escaped) = file::decode(&bytes, encoding)?;
+        let (lines, line_endings, format) = file::split_lines(&text);
+        self.text = Rope::from_str(&lines.join("\n"));
+        let heights: Vec<_> = (0..self.text.len_lines()).map(self.estimator()).collect();
+        let mut line_endings = line_endings
+            .into_iter()
+            .filter(|_| format.mixed_line_endings);
+        self.lines = Paragraphs::new(heights.into_iter().map(|height| {
+            let mut line = TextLine::new();
+            line.line_ending = line_endings.next();
+            (line, height)
+        }));
+        self.format = FileFormat {
+            encoding,
+            bom,
+            escaped,
+            ..format
+        };
+        self.history = Default::default();
+        self.dirty = false;
+        self.viewport = 0..self.viewport.len() as i32;
+        self.content_width = 0;
+        self.generation += 1;
+        self.needs_reshape = true;
+        Ok(())
+    }
+
+    /// Saves the text in its original encoding and line ending style.
+    pub fn save(&self, mut writer: impl Write) -> Result<()> {
+        let mut text = String::with_capacity(self.text.len_bytes());
+        for (i, line) in self.lines.iter().enumerate() {
+            text.extend(self.paragraph_slice(i).chunks());
+            if i + 1 < self.lines.len() || self.format.final_newline {
+                let line_ending = line.line_ending.unwrap_or(self.format.line_ending);
+                text.push_str(line_ending.as_str());
+            }
+        }
+        let format = &self.format;
+        let bytes = file::encode(&text, format.encoding, format.bom, format.escaped)?;
+        writer.write_all(&bytes)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// The first of `FONT_FAMILIES` that is installed, else the system default, else the bundled
+/// `FALLBACK_FONT`.
+fn default_typeface(font_mgr: &FontMgr) -> Typeface {
+    FONT_FAMILIES
+        .iter()
+        .find_map(|family| font_mgr.match_family_style(family, FontStyle::default()))
+        .or_else(|| font_mgr.legacy_make_typeface(None, FontStyle::default()))
+        .or_else(|| font_mgr.new_from_data(FALLBACK_FONT, None))
+        .expect("the bundled font can be loaded")
+}
+
+/// text of paragraph `index` without its newline.
+pub fn paragraph(text: &Rope, index: usize) -> Cow<'_, str> {
+    paragraph_slice(text, index).into()
+}
+
+fn paragraph_slice(text: &Rope, index: usize) -> RopeSlice<'_> {
+    let start = text.line_to_byte(index);
+    let end = if index + 1 < text.len_lines() {
+        text.line_to_byte(index + 1) - 1
+    } else {
+        text.len_bytes()
+    };
+    text.byte_slice(start..end)
+}
+
+/// bytes of the whitespace at the start of `chars`.
+fn whitespace_len(chars: impl Iterator<Item = char>) -> usize {
+    chars
+        .take_while(|c| c.is_whitespace())
+        .map(char::len_utf8)
+        .sum()
+}
+
+fn find_closest_x(line: &TextLine, x: scalar, range: Range<usize>) -> usize {
+    let begin = range.start;
+    range
+        .filter_map(|i| Some((i, line.caret_x(i)?)))
+        .min_by(|(_, a), (_, b)| (a - x).abs().total_cmp(&(b - x).abs()))
+        .map_or(begin, |(i, _)| i)
 }
 
 // note: paragraph first for PartialOrd macro
@@ -399,6 +1183,113 @@ impl TextPosition {
         let end = std::cmp::max(pos1, pos2);
         Range { start, end }
     }
+
+    /// position just after `text` when it is inserted here.
+    pub fn advance(self, text: &str) -> Self {
+        match text.rfind('\n') {
+            None => Self::new(self.text_byte_index + text.len(), self.paragraph_index),
+            Some(i) => Self::new(
+                text.len() - i - 1,
+                self.paragraph_index + text.matches('\n').count(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Selection {
+    pub cursor: TextPosition,
+    pub mark: Option<TextPosition>,
+}
+
+impl Selection {
+    pub fn new(cursor: TextPosition, mark: Option<TextPosition>) -> Self {
+        Self { cursor, mark }
+    }
+}
+
+/// Kind of an undo step. Consecutive steps of the same kind (except `Other`) are merged.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EditKind {
+    Typing,
+    Delete,
+    Other,
+}
+
+enum Edit {
+    Insert(TextPosition, String),
+    Remove(TextPosition, String),
+}
+
+struct Transaction {
+    kind: EditKind,
+    edits: Vec<Edit>,
+    before: Selection,
+    after: Selection,
+}
+
+#[derive(Default)]
+struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    current: Option<Transaction>,
+}
+
+impl History {
+    fn begin(&mut self, kind: EditKind, selection: Selection) {
+        self.close();
+        self.current = Some(Transaction {
+            kind,
+            edits: vec![],
+            before: selection,
+            after: selection,
+        });
+    }
+
+    fn end(&mut self, selection: Selection) {
+        if let Some(current) = &mut self.current {
+            current.after = selection;
+        }
+        self.close();
+    }
+
+    fn close(&mut self) {
+        if let Some(transaction) = self.current.take() {
+            self.push(transaction);
+        }
+    }
+
+    /// `before` and `after` are the cursor positions used when the edit is made outside of a
+    /// transaction.
+    fn record(&mut self, edit: Edit, before: TextPosition, after: TextPosition) {
+        match &mut self.current {
+            Some(current) => current.edits.push(edit),
+            None => self.push(Transaction {
+                kind: EditKind::Other,
+                edits: vec![edit],
+                before: Selection::new(before, None),
+                after: Selection::new(after, None),
+            }),
+        }
+    }
+
+    fn push(&mut self, transaction: Transaction) {
+        if transaction.edits.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+        if let Some(last) = self.undo_stack.last_mut() {
+            if transaction.kind != EditKind::Other
+                && last.kind == transaction.kind
+                && last.after == transaction.before
+            {
+                last.edits.extend(transaction.edits);
+                last.after = transaction.after;
+                return;
+            }
+        }
+        self.undo_stack.push(transaction);
+    }
 }
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
@@ -412,6 +1303,14 @@ pub enum Movement {
     End,
     WordLeft,
     WordRight,
+    /// one code point left if it is a combining mark, otherwise same as `Left`.
+    CodePointLeft,
+    /// past the grapheme cluster left of the caret on screen, whatever its direction; `Left`
+    /// and `Right` move through the text.
+    VisualLeft,
+    VisualRight,
+    DocumentStart,
+    DocumentEnd,
 }
 
 pub struct PaintOpts {
@@ -436,31 +1335,81 @@ impl Default for PaintOpts {
     }
 }
 
-type StringSlice = String;
-type StringView<'a> = &'a str;
+type StringView<'a> = Cow<'a, str>;
 
+/// Shaping results of a paragraph. The text itself lives in `Editor::text`.
 struct TextLine {
-    text: StringSlice,
     blob: Option<TextBlob>,
     cursor_pos: Vec<Rect>,
     line_end_offsets: Vec<usize>,
     word_boundaries: Vec<bool>,
-    origin: IPoint,
-    height: i32,
+    /// direction of the grapheme cluster at each byte, then of the paragraph.
+    rtl: Vec<bool>,
     shaped: bool,
+    /// bumped on every change, so that stale results of the worker are dropped.
+    revision: u64,
+    /// generation in which the line was sent to the worker.
+    requested: Option<u64>,
+    /// line ending kept from a file with mixed ones; `FileFormat::line_ending` otherwise.
+    line_ending: Option<LineEnding>,
 }
 
 impl TextLine {
-    fn new(text: StringSlice) -> Self {
+    fn new() -> Self {
         Self {
-            text,
             blob: Default::default(),
             cursor_pos: Default::default(),
             line_end_offsets: Default::default(),
             word_boundaries: Default::default(),
-            origin: Default::default(),
-            height: Default::default(),
+            rtl: Default::default(),
             shaped: Default::default(),
+            revision: Default::default(),
+            requested: Default::default(),
+            line_ending: Default::default(),
         }
     }
+
+    fn is_rtl(&self, index: usize) -> bool {
+        self.rtl.get(index) == Some(&true)
+    }
+
+    /// x of the caret before the grapheme cluster at `index`, which is on its right when the
+    /// cluster is right-to-left.
+    fn caret_x(&self, index: usize) -> Option<scalar> {
+        let rect = self
+            .cursor_pos
+            .get(index)
+            .filter(|&rect| *rect != UNSET_RECT)?;
+        Some(if self.is_rtl(index) {
+            rect.right
+        } else {
+            rect.left
+        })
+    }
+
+    fn is_word_boundary(&self, index: usize, len: usize) -> bool {
+        index == 0 || index >= len || self.word_boundaries.get(index) == Some(&true)
+    }
+
+    fn prev_word_boundary(&self, index: usize, len: usize) -> usize {
+        (0..index)
+            .rev()
+            .find(|&i| self.is_word_boundary(i, len))
+            .unwrap_or(0)
+    }
+
+    fn next_word_boundary(&self, index: usize, len: usize) -> usize {
+        (index + 1..=len)
+            .find(|&i| self.is_word_boundary(i, len))
+            .unwrap_or(len)
+    }
+
+    /// byte range of the soft-wrapped line containing `index`, in a paragraph of `len` bytes.
+    fn visual_line(&self, index: usize, len: usize) -> Range<usize> {
+        let list = &self.line_end_offsets;
+        let f = list.partition_point(|&offset| offset <= index);
+        let start = if f == 0 { 0 } else { list[f - 1] };
+        let end = list.get(f).copied().unwrap_or(len);
+        start..end
+    }
 }
@@ -0,0 +1,53 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// a character that the file's encoding cannot represent.
+    Encoding {
+        c: char,
+        encoding: &'static str,
+    },
+    FileTooLarge {
+        limit: u64,
+    },
+    /// a file with invalid bytes that also contains the characters they would be escaped to.
+    AmbiguousEscapes {
+        encoding: &'static str,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Encoding { c, encoding } => write!(f, "{c:?} cannot be encoded in {encoding}"),
+            Error::FileTooLarge { limit } => {
+                write!(f, "file is larger than {} MiB", limit >> 20)
+            }
+            Error::AmbiguousEscapes { encoding } => write!(
+                f,
+                "file has bytes that are invalid in {encoding} and characters U+10FF00 to \
+                 U+10FFFF, which would be confused with them"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,360 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::error::{Error, Result};
+
+/// Writes `data` to a temporary file next to `path` and renames it over `path`, so that a crash
+/// never leaves a half-written file behind. The permissions of the replaced file are kept.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    // write through symlinks instead of replacing them.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a file path",
+        ));
+    };
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = dir.join(temp_name);
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(data)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, &path)
+    })();
+    if result.is_err() {
+        _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    /// next style for the conversion command.
+    pub fn next(self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Cr,
+            LineEnding::Cr => LineEnding::Lf,
+        }
+    }
+}
+
+/// How a document is laid out on disk, detected on load and reproduced on save.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileFormat {
+    pub encoding: &'static Encoding,
+    /// the most common line ending, used for new lines and, unless the file mixed them, for every
+    /// line on save.
+    pub line_ending: LineEnding,
+    /// the file used more than one line ending style, which are kept for each line.
+    pub mixed_line_endings: bool,
+    pub bom: bool,
+    pub final_newline: bool,
+    /// invalid bytes were decoded to escapes, which are written back as the bytes on save.
+    pub escaped: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            line_ending: Default::default(),
+            mixed_line_endings: false,
+            bom: false,
+            final_newline: true,
+            escaped: false,
+        }
+    }
+}
+
+impl FileFormat {
+    pub fn name(&self) -> String {
+        let mut name = format!("{} {}", self.encoding.name(), self.line_ending.name());
+        if self.mixed_line_endings {
+            name += " (mixed)";
+        }
+        if self.bom {
+            name += " BOM";
+        }
+        name
+    }
+}
+
+/// Splits `text` into paragraphs on `\n`, `\r\n` and `\r`, and detects its line endings.
+/// Also returns the line ending of each paragraph that has one.
+pub fn split_lines(text: &str) -> (Vec<&str>, Vec<LineEnding>, FileFormat) {
+    let bytes = text.as_bytes();
+    let mut lines = vec![];
+    let mut line_endings = vec![];
+    let mut counts = [0; 3];
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let (line_ending, len) = match bytes[i] {
+            b'\n' => (LineEnding::Lf, 1),
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => (LineEnding::CrLf, 2),
+            b'\r' => (LineEnding::Cr, 1),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        counts[line_ending as usize] += 1;
+        lines.push(&text[start..i]);
+        line_endings.push(line_ending);
+        i += len;
+        start = i;
+    }
+    let final_newline = !lines.is_empty() && start == text.len();
+    if !final_newline {
+        lines.push(&text[start..]);
+    }
+
+    let line_ending = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr]
+        .into_iter()
+        .rev()
+        .max_by_key(|&line_ending| counts[line_ending as usize])
+        .unwrap();
+    let format = FileFormat {
+        line_ending,
+        mixed_line_endings: counts.iter().filter(|&&count| count > 0).count() > 1,
+        final_newline,
+        ..Default::default()
+    };
+    (lines, line_endings, format)
+}
+
+/// `text` with its `\r\n` and `\r` line endings turned into `\n`, which alone separates
+/// paragraphs, for text that doesn't come from a file.
+pub fn normalize_line_endings(text: &str) -> Cow<'_, str> {
+    if !text.contains('\r') {
+        return text.into();
+    }
+    text.replace("\r\n", "\n").replace('\r', "\n").into()
+}
+
+/// Encodings offered by the encoding override command.
+pub fn encodings() -> [&'static Encoding; 6] {
+    use encoding_rs::{EUC_JP, SHIFT_JIS, WINDOWS_1252};
+    [UTF_8, SHIFT_JIS, EUC_JP, UTF_16LE, UTF_16BE, WINDOWS_1252]
+}
+
+// Bytes that are invalid in the file's encoding are decoded to U+10FF00 + byte and written back
+// as the same byte, so that loading and saving never changes them. Only files with invalid bytes
+// are escaped, and those can't also contain the escape characters themselves.
+const ESCAPE_BASE: u32 = 0x10FF00;
+
+fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE + byte as u32).unwrap()
+}
+
+fn unescape(c: char) -> Option<u8> {
+    (c as u32).checked_sub(ESCAPE_BASE).map(|byte| byte as u8)
+}
+
+/// Decodes `bytes` with `encoding`, or with the encoding detected from the BOM or the content.
+/// Returns the text, the encoding used, whether a BOM was found and whether invalid bytes were
+/// escaped.
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> Result<(String, &'static Encoding, bool, bool)> {
+    let (encoding, bom_length) = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, length)) if encoding.is_none_or(|e| e == bom_encoding) => {
+            (bom_encoding, length)
+        }
+        _ => (encoding.unwrap_or_else(|| detect(bytes)), 0),
+    };
+    let bytes = &bytes[bom_length..];
+
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(bytes.len());
+    let mut offset = 0;
+    let mut escaped = false;
+    // valid text that looks like escapes.
+    let mut ambiguous = false;
+    loop {
+        if let Some(length) =
+            decoder.max_utf8_buffer_length_without_replacement(bytes.len() - offset)
+        {
+            text.reserve(length);
+        }
+        let decoded = text.len();
+        let (result, read) =
+            decoder.decode_to_string_without_replacement(&bytes[offset..], &mut text, true);
+        offset += read;
+        ambiguous |= text[decoded..].chars().any(|c| unescape(c).is_some());
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::OutputFull => {}
+            DecoderResult::Malformed(malformed, consumed_after) => {
+                let end = offset - consumed_after as usize;
+                let start = end - malformed as usize;
+                text.extend(bytes[start..end].iter().map(|&byte| escape(byte)));
+                escaped = true;
+            }
+        }
+    }
+    if escaped && ambiguous {
+        return Err(Error::AmbiguousEscapes {
+            encoding: encoding.name(),
+        });
+    }
+    Ok((text, encoding, bom_length > 0, escaped))
+}
+
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    // NUL is valid UTF-8, so check for UTF-16 first.
+    if let Some(encoding) = detect_utf16(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+/// BOM-less UTF-16 is recognized by the zero high bytes of Latin text.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    let units = sample.len() / 2;
+    if units == 0 {
+        return None;
+    }
+    let zeros = |parity| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&byte| byte == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    if odd * 5 > units * 2 && even * 20 < units {
+        Some(UTF_16LE)
+    } else if even * 5 > units * 2 && odd * 20 < units {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Byte order mark of `encoding`, if it is a Unicode encoding.
+pub fn bom(encoding: &'static Encoding) -> Option<&'static [u8]> {
+    match encoding {
+        e if e == UTF_8 => Some(b"\xEF\xBB\xBF"),
+        e if e == UTF_16LE => Some(b"\xFF\xFE"),
+        e if e == UTF_16BE => Some(b"\xFE\xFF"),
+        _ => None,
+    }
+}
+
+/// Encodes `text` back to `encoding`, restoring invalid bytes if they were `escaped`. `bom` is
+/// ignored for encodings without one.
+pub fn encode(
+    text: &str,
+    encoding: &'static Encoding,
+    bom: bool,
+    escaped: bool,
+) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    if bom {
+        bytes.extend_from_slice(self::bom(encoding).unwrap_or_default());
+    }
+    let mut encoder = encoding.new_encoder();
+    let mut rest = text;
+    loop {
+        match rest
+            .char_indices()
+            .find_map(|(i, c)| Some((i, c, unescape(c).filter(|_| escaped)?)))
+        {
+            Some((i, c, byte)) => {
+                encode_str(&mut encoder, encoding, &rest[..i], &mut bytes, false)?;
+                bytes.push(byte);
+                rest = &rest[i + c.len_utf8()..];
+            }
+            None => {
+                encode_str(&mut encoder, encoding, rest, &mut bytes, true)?;
+                return Ok(bytes);
+            }
+        }
+    }
+}
+
+fn encode_str(
+    encoder: &mut Encoder,
+    encoding: &'static Encoding,
+    mut text: &str,
+    bytes: &mut Vec<u8>,
+    last: bool,
+) -> Result<()> {
+    // encoding_rs only decodes UTF-16.
+    if encoding == UTF_16LE {
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        return Ok(());
+    }
+    if encoding == UTF_16BE {
+        bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        return Ok(());
+    }
+    loop {
+        if let Some(length) = encoder.max_buffer_length_from_utf8_without_replacement(text.len()) {
+            bytes.reserve(length);
+        }
+        let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(text, bytes, last);
+        text = &text[read..];
+        match result {
+            EncoderResult::InputEmpty => return Ok(()),
+            EncoderResult::OutputFull => {}
+            EncoderResult::Unmappable(c) => {
+                return Err(Error::Encoding {
+                    c,
+                    encoding: encoding.name(),
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use skia::{Canvas, Color4f, Font, FontMgr, FontStyle, Paint, Rect, Typeface};
+
+/// families shown at once below the query.
+const ROWS: usize = 10;
+
+/// A list of font families filtered by a typed query, each previewed in its own face.
+pub struct FontPicker {
+    families: Vec<String>,
+    query: String,
+    /// indices into `families` that contain `query`, ignoring case.
+    matches: Vec<usize>,
+    /// index into `matches`.
+    selected: usize,
+    /// first row shown, as an index into `matches`.
+    first: usize,
+    /// typefaces by family index; `None` for families that can't show their own name.
+    previews: HashMap<usize, Option<Typeface>>,
+}
+
+impl FontPicker {
+    /// Lists `families` with `selected` selected.
+    pub fn new(families: Vec<String>, selected: usize) -> Self {
+        let mut picker = Self {
+            families,
+            query: String::new(),
+            matches: vec![],
+            selected: 0,
+            first: 0,
+            previews: HashMap::new(),
+        };
+        picker.filter();
+        picker.selected = selected.min(picker.matches.len().saturating_sub(1));
+        picker.scroll_to_selected();
+        picker
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.filter();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.filter();
+    }
+
+    /// Moves the selection by `rows`, stopping at the first and last match.
+    pub fn move_by(&mut self, rows: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(rows).min(last);
+        self.scroll_to_selected();
+    }
+
+    pub fn move_by_pages(&mut self, pages: isize) {
+        self.move_by(pages * ROWS as isize);
+    }
+
+    /// index of the selected family in the list given to `new`.
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.selected).copied()
+    }
+
+    /// Paints the query and the visible matches in a panel `width` wide at `(x, y)`. Families
+    /// are previewed at the size of `font`, which is also used for the query.
+    pub fn paint(
+        &mut self,
+        canvas: &Canvas,
+        font_mgr: &FontMgr,
+        font: &Font,
+        (x, y): (f32, f32),
+        width: f32,
+        margin: f32,
+    ) {
+        let row_height = font.spacing().ceil() + margin;
+        let rows = self.matches.len().clamp(1, ROWS);
+        let bounds = Rect::from_xywh(x, y, width, row_height * (rows + 1) as f32);
+        canvas.draw_round_rect(
+            bounds,
+            margin / 2.,
+            margin / 2.,
+            &Paint::new(Color4f::new(0.2, 0.2, 0.2, 0.95), None),
+        );
+        let white = Paint::new(Color4f::new(1., 1., 1., 1.), None);
+        let baseline = |font: &Font, row: usize| {
+            let (_, metrics) = font.metrics();
+            y + row_height * (row + 1) as f32 - margin / 2. - metrics.descent
+        };
+        canvas.draw_str(
+            format!("Font: {}", self.query),
+            (x + margin, baseline(font, 0)),
+            font,
+            &white,
+        );
+        if self.matches.is_empty() {
+            canvas.draw_str(
+                "no matching fonts",
+                (x + margin, baseline(font, 1)),
+                font,
+                &Paint::new(Color4f::new(0.6, 0.6, 0.6, 1.), None),
+            );
+            return;
+        }
+
+        canvas.save();
+        canvas.clip_rect(bounds, None, None);
+        for row in 0..rows {
+            let Some(&index) = self.matches.get(self.first + row) else {
+                break;
+            };
+            let top = y + row_height * (row + 1) as f32;
+            if self.first + row == self.selected {
+                canvas.draw_rect(
+                    Rect::from_xywh(x, top, width, row_height),
+                    &Paint::new(Color4f::new(0.3, 0.4, 0.7, 1.), None),
+                );
+            }
+            let mut preview = font.clone();
+            if let Some(typeface) = self.preview(font_mgr, index) {
+                preview.set_typeface(typeface);
+            }
+            canvas.draw_str(
+                &self.families[index],
+                (x + margin, baseline(&preview, row + 1)),
+                &preview,
+                &white,
+            );
+        }
+        canvas.restore();
+    }
+
+    fn preview(&mut self, font_mgr: &FontMgr, index: usize) -> Option<Typeface> {
+        let family = &self.families[index];
+        self.previews
+            .entry(index)
+            .or_insert_with(|| {
+                let typeface = font_mgr.match_family_style(family, FontStyle::default())?;
+                // symbol fonts have no glyphs for the letters of their name.
+                let glyphs = Font::from_typeface(typeface.clone(), None).str_to_glyphs_vec(family);
+                (!glyphs.contains(&0)).then_some(typeface)
+            })
+            .clone()
+    }
+
+    fn filter(&mut self) {
+        // keep the selected family selected while it still matches.
+        let selected = self.selected();
+        let query = self.query.to_lowercase();
+        self.matches = (0..self.families.len())
+            .filter(|&i| self.families[i].to_lowercase().contains(&query))
+            .collect();
+        self.selected = selected
+            .and_then(|selected| self.matches.iter().position(|&i| i == selected))
+            .unwrap_or(0);
+        self.scroll_to_selected();
+    }
+
+    fn scroll_to_selected(&mut self) {
+        self.first = self
+            .first
+            .clamp(self.selected.saturating_sub(ROWS - 1), self.selected);
+    }
+}
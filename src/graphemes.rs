@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+use ropey::RopeSlice;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+// Grapheme cluster boundaries of a `RopeSlice`, found chunk by chunk so that long paragraphs are
+// never copied into one string. Byte indices must be on char boundaries.
+
+pub fn prev_boundary(slice: RopeSlice<'_>, byte_index: usize) -> usize {
+    let (mut chunk, mut chunk_start, _, _) = slice.chunk_at_byte(byte_index);
+    let mut cursor = GraphemeCursor::new(byte_index, slice.len_bytes(), true);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_start) {
+            Ok(None) => return 0,
+            Ok(Some(n)) => return n,
+            Err(GraphemeIncomplete::PrevChunk) => {
+                (chunk, chunk_start, _, _) = slice.chunk_at_byte(chunk_start - 1);
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let (context, context_start, _, _) = slice.chunk_at_byte(n - 1);
+                cursor.provide_context(context, context_start);
+            }
+            Err(e) => unreachable!("{e:?}"),
+        }
+    }
+}
+
+pub fn next_boundary(slice: RopeSlice<'_>, byte_index: usize) -> usize {
+    let (mut chunk, mut chunk_start, _, _) = slice.chunk_at_byte(byte_index);
+    let mut cursor = GraphemeCursor::new(byte_index, slice.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_start) {
+            Ok(None) => return slice.len_bytes(),
+            Ok(Some(n)) => return n,
+            Err(GraphemeIncomplete::NextChunk) => {
+                (chunk, chunk_start, _, _) = slice.chunk_at_byte(chunk_start + chunk.len());
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let (context, context_start, _, _) = slice.chunk_at_byte(n - 1);
+                cursor.provide_context(context, context_start);
+            }
+            Err(e) => unreachable!("{e:?}"),
+        }
+    }
+}
+
+pub fn is_boundary(slice: RopeSlice<'_>, byte_index: usize) -> bool {
+    let (chunk, chunk_start, _, _) = slice.chunk_at_byte(byte_index);
+    let mut cursor = GraphemeCursor::new(byte_index, slice.len_bytes(), true);
+    loop {
+        match cursor.is_boundary(chunk, chunk_start) {
+            Ok(boundary) => return boundary,
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let (context, context_start, _, _) = slice.chunk_at_byte(n - 1);
+                cursor.provide_context(context, context_start);
+            }
+            Err(e) => unreachable!("{e:?}"),
+        }
+    }
+}
+
+/// Byte index of the char containing `byte_index`, which may be past the end.
+pub fn floor_char_boundary(slice: RopeSlice<'_>, byte_index: usize) -> usize {
+    let byte_index = byte_index.min(slice.len_bytes());
+    slice.char_to_byte(slice.byte_to_char(byte_index))
+}
+
+/// byte ranges of the grapheme clusters in `range`, which must start and end on boundaries.
+pub fn clusters(
+    slice: RopeSlice<'_>,
+    range: Range<usize>,
+) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = range.start;
+    std::iter::from_fn(move || {
+        if start >= range.end {
+            return None;
+        }
+        let end = next_boundary(slice, start).min(range.end);
+        Some(std::mem::replace(&mut start, end)..end)
+    })
+}
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Velocity decay rate per second; the fling keeps `exp(-FRICTION)` of its speed after 1s.
+const FRICTION: f32 = 4.;
+/// Speed in pixels per second below which a fling stops.
+const MIN_SPEED: f32 = 20.;
+/// How far back `VelocityTracker` looks.
+const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+/// Inertial scrolling that slows down with friction after a touch drag is released.
+/// Time is passed in by the caller, so that the motion follows the event timestamps.
+#[derive(Debug, Copy, Clone)]
+pub struct Fling {
+    /// pixels per second.
+    velocity: (f32, f32),
+    last: Instant,
+}
+
+impl Fling {
+    pub fn new(velocity: (f32, f32), now: Instant) -> Option<Self> {
+        (speed(velocity) >= MIN_SPEED).then_some(Self {
+            velocity,
+            last: now,
+        })
+    }
+
+    /// Advances the fling to `now` and returns the distance travelled since the last step, or
+    /// `None` once it is too slow to continue.
+    pub fn step(&mut self, now: Instant) -> Option<(f32, f32)> {
+        if speed(self.velocity) < MIN_SPEED {
+            return None;
+        }
+        let dt = now.saturating_duration_since(self.last).as_secs_f32();
+        self.last = now;
+        // the integral of v * exp(-FRICTION * t) over dt.
+        let decay = (-FRICTION * dt).exp();
+        let travelled = (1. - decay) / FRICTION;
+        let (x, y) = self.velocity;
+        self.velocity = (x * decay, y * decay);
+        Some((x * travelled, y * travelled))
+    }
+}
+
+/// Estimates the velocity of a pointer from its recent positions.
+#[derive(Debug, Default, Clone)]
+pub struct VelocityTracker {
+    samples: VecDeque<(Instant, (f32, f32))>,
+}
+
+impl VelocityTracker {
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn add(&mut self, now: Instant, position: (f32, f32)) {
+        while let Some(&(time, _)) = self.samples.front() {
+            if now.saturating_duration_since(time) <= VELOCITY_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, position));
+    }
+
+    /// pixels per second over the samples of the last `VELOCITY_WINDOW` before `now`.
+    pub fn velocity(&self, now: Instant) -> (f32, f32) {
+        let recent = || {
+            self.samples
+                .iter()
+                .filter(|(time, _)| now.saturating_duration_since(*time) <= VELOCITY_WINDOW)
+        };
+        let (Some(&(t0, (x0, y0))), Some(&(t1, (x1, y1)))) =
+            (recent().next(), recent().next_back())
+        else {
+            return (0., 0.);
+        };
+        let dt = t1.saturating_duration_since(t0).as_secs_f32();
+        if dt == 0. {
+            return (0., 0.);
+        }
+        ((x1 - x0) / dt, (y1 - y0) / dt)
+    }
+}
+
+fn speed((x, y): (f32, f32)) -> f32 {
+    x.hypot(y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn fling_travels_velocity_over_friction() {
+        let start = Instant::now();
+        let mut fling = Fling::new((400., -200.), start).unwrap();
+        let (x, y) = fling.step(start + Duration::from_secs(10)).unwrap();
+        assert!(approx_eq(x, 400. / FRICTION, 0.01));
+        assert!(approx_eq(y, -200. / FRICTION, 0.01));
+        assert_eq!(fling.step(start + Duration::from_secs(11)), None);
+    }
+
+    #[test]
+    fn fling_steps_add_up() {
+        let start = Instant::now();
+        let mut fling = Fling::new((0., 400.), start).unwrap();
+        let mut travelled = 0.;
+        let mut now = start;
+        loop {
+            now += Duration::from_millis(16);
+            let Some((_, y)) = fling.step(now) else {
+                break;
+            };
+            travelled += y;
+        }
+        // the fling stops once slower than `MIN_SPEED`, which leaves out at most this much.
+        assert!(travelled <= 400. / FRICTION);
+        assert!(travelled >= (400. - MIN_SPEED) / FRICTION - 0.01);
+    }
+
+    #[test]
+    fn slow_fling_does_not_start() {
+        let now = Instant::now();
+        assert!(Fling::new((MIN_SPEED / 2., 0.), now).is_none());
+        assert!(Fling::new((0., 0.), now).is_none());
+    }
+
+    #[test]
+    fn tracker_drops_old_samples() {
+        let start = Instant::now();
+        let mut tracker = VelocityTracker::default();
+        tracker.add(start, (0., 0.));
+        tracker.add(start + Duration::from_millis(200), (0., 1000.));
+        tracker.add(start + Duration::from_millis(250), (0., 1050.));
+        assert_eq!(tracker.samples.len(), 2);
+        let (x, y) = tracker.velocity(start + Duration::from_millis(250));
+        assert!(approx_eq(x, 0., 0.01));
+        assert!(approx_eq(y, 1000., 0.5));
+    }
+
+    #[test]
+    fn tracker_needs_two_recent_samples() {
+        let start = Instant::now();
+        let mut tracker = VelocityTracker::default();
+        assert_eq!(tracker.velocity(start), (0., 0.));
+        tracker.add(start, (0., 0.));
+        tracker.add(start + Duration::from_millis(50), (100., 0.));
+        assert_eq!(tracker.velocity(start + Duration::from_secs(1)), (0., 0.));
+    }
+}
@@ -1,4 +1,5 @@
 use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 
 use arboard::Clipboard;
 use skia::Rect;
@@ -6,20 +7,71 @@ use winit::dpi::PhysicalPosition;
 use winit::window::CursorIcon;
 use winit::{
     dpi::PhysicalSize,
-    event::{ElementState, Event, Modifiers, WindowEvent},
-    event_loop::EventLoop,
+    event::{ElementState, Event, Modifiers, Touch, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder},
     keyboard::Key,
     window::WindowBuilder,
 };
 
-use crate::app::{Context, EditorLayer, Layer};
+use crate::app::{Context, EditorLayer, Layer, UserEvent};
+use crate::worker::ShapeWorker;
 
 mod app;
+mod cache;
 mod editor;
+mod error;
+mod file;
+mod font_picker;
+mod graphemes;
+mod kinetic;
+mod paragraphs;
+mod settings;
 mod shape;
+mod worker;
+
+const USAGE: &str = "usage: skia-plain-text-editor [FILE] [+LINE[:COL]]";
+/// environment variable that prints the shape cache's hit rate on exit when set.
+const SHAPE_CACHE_STATS: &str = "SKIA_EDITOR_SHAPE_CACHE_STATS";
+
+struct Args {
+    /// file to open, `-` for stdin.
+    path: Option<String>,
+    /// 1-based line and column of the caret.
+    position: (usize, usize),
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut path = None;
+    let mut position = (1, 1);
+    for arg in args {
+        if let Some(line_column) = arg.strip_prefix('+') {
+            let (line, column) = line_column.split_once(':').unwrap_or((line_column, "1"));
+            let parse = |s: &str| {
+                s.parse::<usize>()
+                    .map_err(|_| format!("invalid position: {arg}"))
+            };
+            position = (parse(line)?, parse(column)?);
+        } else if path.is_none() {
+            path = Some(arg);
+        } else {
+            return Err(format!("unexpected argument: {arg}"));
+        }
+    }
+    Ok(Args { path, position })
+}
 
 fn main() -> Result<(), impl std::error::Error> {
-    let event_loop = EventLoop::new().unwrap();
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
+        .build()
+        .unwrap();
 
     let window = WindowBuilder::new()
         .with_title("skia-plain-text-editor")
@@ -41,20 +93,48 @@ fn main() -> Result<(), impl std::error::Error> {
     let ctx = &mut Context {
         window: &window,
         clipboard: Clipboard::new().unwrap(),
+        wake_up: None,
     };
     ctx.set_cursor_icon(CursorIcon::Text);
     let mut layer = EditorLayer::new();
+    layer
+        .editor
+        .set_shape_worker(ShapeWorker::new(event_loop.create_proxy()));
     layer.on_resize(ctx, (width as _, height as _));
+    if let Some(path) = &args.path {
+        layer.open(ctx, path, args.position);
+    }
 
     event_loop.run(|event, elwt| {
         if let Event::WindowEvent { event, .. } = event {
             match event {
-                WindowEvent::CloseRequested => elwt.exit(),
+                WindowEvent::CloseRequested => {
+                    if std::env::var_os(SHAPE_CACHE_STATS).is_some() {
+                        eprintln!("shape cache: {}", layer.editor.shape_cache_stats());
+                    }
+                    elwt.exit();
+                }
                 WindowEvent::ModifiersChanged(m) => {
                     modifiers = m;
                 }
                 WindowEvent::CursorMoved { position, .. } => {
                     cursor_pos = position;
+                    let PhysicalPosition { x, y } = cursor_pos;
+                    layer.on_mouse_move(ctx, (x as _, y as _), modifiers);
+                }
+                WindowEvent::Touch(Touch {
+                    id,
+                    phase,
+                    location,
+                    ..
+                }) => {
+                    layer.on_touch(ctx, id, phase, (location.x as _, location.y as _));
+                }
+                WindowEvent::TouchpadMagnify { delta, .. } => {
+                    layer.on_pinch(ctx, 1. + delta as f32);
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    layer.on_mouse_wheel(ctx, delta, modifiers);
                 }
                 WindowEvent::MouseInput { state, .. } => {
                     let PhysicalPosition { x, y } = cursor_pos;
@@ -95,6 +175,17 @@ fn main() -> Result<(), impl std::error::Error> {
                 }
                 _ => {}
             }
+        } else if let Event::UserEvent(UserEvent::Shaped(shaped)) = event {
+            layer.on_shaped(ctx, shaped);
+        } else if let Event::AboutToWait = event {
+            if ctx.wake_up.is_some_and(|wake_up| wake_up <= Instant::now()) {
+                ctx.wake_up = None;
+                layer.on_idle(ctx);
+            }
+            elwt.set_control_flow(match ctx.wake_up {
+                Some(wake_up) => ControlFlow::WaitUntil(wake_up),
+                None => ControlFlow::Wait,
+            });
         }
     })
 }
@@ -0,0 +1,289 @@
+use std::ops::{Index, IndexMut, Range};
+
+/// Per-paragraph data and heights in a treap ordered by paragraph index, so that inserting and
+/// removing paragraphs, the y of a paragraph and the paragraph at a y all take O(log n).
+pub struct Paragraphs<T> {
+    root: Link<T>,
+    /// state of the xorshift generator of node priorities.
+    seed: u64,
+}
+
+type Link<T> = Option<Box<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    height: i32,
+    /// the parent of a node has a higher priority than it.
+    priority: u64,
+    /// paragraphs in this subtree.
+    len: usize,
+    /// sum of the heights in this subtree.
+    total: i32,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+impl<T> Node<T> {
+    fn update(&mut self) {
+        self.len = 1 + len(&self.left) + len(&self.right);
+        self.total = self.height + total(&self.left) + total(&self.right);
+    }
+}
+
+fn len<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |node| node.len)
+}
+
+fn total<T>(link: &Link<T>) -> i32 {
+    link.as_ref().map_or(0, |node| node.total)
+}
+
+/// Splits `link` into its first `index` paragraphs and the rest.
+fn split<T>(link: Link<T>, index: usize) -> (Link<T>, Link<T>) {
+    let Some(mut node) = link else {
+        return (None, None);
+    };
+    let left_len = len(&node.left);
+    if index <= left_len {
+        let (first, rest) = split(node.left.take(), index);
+        node.left = rest;
+        node.update();
+        (first, Some(node))
+    } else {
+        let (first, rest) = split(node.right.take(), index - left_len - 1);
+        node.right = first;
+        node.update();
+        (Some(node), rest)
+    }
+}
+
+/// Joins `first` and then `rest` into one tree.
+fn merge<T>(first: Link<T>, rest: Link<T>) -> Link<T> {
+    match (first, rest) {
+        (None, link) | (link, None) => link,
+        (Some(mut first), Some(mut rest)) => {
+            if first.priority > rest.priority {
+                first.right = merge(first.right.take(), Some(rest));
+                first.update();
+                Some(first)
+            } else {
+                rest.left = merge(Some(first), rest.left.take());
+                rest.update();
+                Some(rest)
+            }
+        }
+    }
+}
+
+fn for_each_mut<T>(link: &mut Link<T>, first: usize, f: &mut impl FnMut(usize, &mut T, &mut i32)) {
+    let Some(node) = link else {
+        return;
+    };
+    for_each_mut(&mut node.left, first, f);
+    let index = first + len(&node.left);
+    f(index, &mut node.value, &mut node.height);
+    for_each_mut(&mut node.right, index + 1, f);
+    node.update();
+}
+
+impl<T> Paragraphs<T> {
+    /// Builds the tree from paragraphs and their heights in O(n).
+    pub fn new(paragraphs: impl IntoIterator<Item = (T, i32)>) -> Self {
+        let mut tree = Self {
+            root: None,
+            seed: 0x9E37_79B9_7F4A_7C15,
+        };
+        tree.root = tree.build(paragraphs);
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        len(&self.root)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.node(index).map(|node| &node.value)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let mut link = &mut self.root;
+        let mut index = index;
+        while let Some(node) = link {
+            let left_len = len(&node.left);
+            if index < left_len {
+                link = &mut node.left;
+            } else if index == left_len {
+                return Some(&mut node.value);
+            } else {
+                index -= left_len + 1;
+                link = &mut node.right;
+            }
+        }
+        None
+    }
+
+    pub fn height(&self, index: usize) -> i32 {
+        self.node(index).expect("paragraph index in range").height
+    }
+
+    pub fn set_height(&mut self, index: usize, height: i32) {
+        fn set<T>(node: &mut Node<T>, index: usize, height: i32) {
+            let left_len = len(&node.left);
+            if index < left_len {
+                set(node.left.as_mut().unwrap(), index, height);
+            } else if index == left_len {
+                node.height = height;
+            } else {
+                set(node.right.as_mut().unwrap(), index - left_len - 1, height);
+            }
+            node.update();
+        }
+        assert!(index < self.len(), "paragraph index out of range");
+        set(self.root.as_mut().unwrap(), index, height);
+    }
+
+    /// Replaces the paragraphs in `range`, in O(log n) plus the paragraphs added and removed.
+    pub fn splice(&mut self, range: Range<usize>, paragraphs: impl IntoIterator<Item = (T, i32)>) {
+        let (first, rest) = split(self.root.take(), range.end);
+        let (first, removed) = split(first, range.start);
+        drop(removed);
+        let inserted = self.build(paragraphs);
+        self.root = merge(merge(first, inserted), rest);
+    }
+
+    /// Calls `f` with the index, data and height of every paragraph in order.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(usize, &mut T, &mut i32)) {
+        for_each_mut(&mut self.root, 0, &mut f);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut stack = vec![];
+        let mut link = self.root.as_deref();
+        std::iter::from_fn(move || {
+            while let Some(node) = link {
+                stack.push(node);
+                link = node.left.as_deref();
+            }
+            let node = stack.pop()?;
+            link = node.right.as_deref();
+            Some(&node.value)
+        })
+    }
+
+    /// y of paragraph `index`, i.e. the sum of the heights before it.
+    pub fn offset(&self, index: usize) -> i32 {
+        let mut sum = 0;
+        let mut link = &self.root;
+        let mut index = index;
+        while let Some(node) = link {
+            let left_len = len(&node.left);
+            if index <= left_len {
+                link = &node.left;
+            } else {
+                sum += total(&node.left) + node.height;
+                index -= left_len + 1;
+                link = &node.right;
+            }
+        }
+        sum
+    }
+
+    pub fn total(&self) -> i32 {
+        total(&self.root)
+    }
+
+    /// index of the paragraph containing `y`, clamped to the existing paragraphs.
+    pub fn find(&self, y: i32) -> usize {
+        let mut index = 0;
+        let mut rest = y;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left_total = total(&node.left);
+            if rest < left_total {
+                link = &node.left;
+            } else if rest < left_total + node.height {
+                return index + len(&node.left);
+            } else {
+                rest -= left_total + node.height;
+                index += len(&node.left) + 1;
+                link = &node.right;
+            }
+        }
+        index.min(self.len().saturating_sub(1))
+    }
+
+    fn node(&self, index: usize) -> Option<&Node<T>> {
+        let mut link = &self.root;
+        let mut index = index;
+        while let Some(node) = link {
+            let left_len = len(&node.left);
+            if index < left_len {
+                link = &node.left;
+            } else if index == left_len {
+                return Some(node);
+            } else {
+                index -= left_len + 1;
+                link = &node.right;
+            }
+        }
+        None
+    }
+
+    /// Builds a treap of `paragraphs` in O(n), keeping the right spine on a stack.
+    fn build(&mut self, paragraphs: impl IntoIterator<Item = (T, i32)>) -> Link<T> {
+        let mut spine: Vec<Box<Node<T>>> = vec![];
+        for (value, height) in paragraphs {
+            let mut node = Box::new(Node {
+                value,
+                height,
+                priority: self.next_priority(),
+                len: 1,
+                total: height,
+                left: None,
+                right: None,
+            });
+            // nodes of lower priority end up in the left subtree of the new one.
+            let mut left = None;
+            while spine
+                .last()
+                .is_some_and(|last| last.priority < node.priority)
+            {
+                let mut last = spine.pop().unwrap();
+                last.right = left;
+                last.update();
+                left = Some(last);
+            }
+            node.left = left;
+            node.update();
+            spine.push(node);
+        }
+        let mut root = None;
+        while let Some(mut node) = spine.pop() {
+            node.right = root;
+            node.update();
+            root = Some(node);
+        }
+        root
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl<T> Index<usize> for Paragraphs<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("paragraph index in range")
+    }
+}
+
+impl<T> IndexMut<usize> for Paragraphs<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("paragraph index in range")
+    }
+}
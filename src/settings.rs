@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file;
+
+/// Preferences kept across sessions, stored as `key=value` lines in the user's config directory.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    pub font_family: Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings file. A missing or unreadable file gives the defaults, and unknown keys
+    /// are ignored.
+    pub fn load() -> Self {
+        let mut settings = Self::default();
+        let Some(text) = path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return settings;
+        };
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("font_family", family)) if !family.is_empty() => {
+                    settings.font_family = Some(family.to_string());
+                }
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = path() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no config directory",
+            ));
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        if let Some(family) = &self.font_family {
+            text += &format!("font_family={family}\n");
+        }
+        file::write_atomic(&path, text.as_bytes())
+    }
+}
+
+/// `%APPDATA%` on Windows, `$XDG_CONFIG_HOME` or `~/.config` elsewhere.
+pub fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    Some(
+        config_dir()?
+            .join("skia-plain-text-editor")
+            .join("settings"),
+    )
+}
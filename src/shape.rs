@@ -1,10 +1,12 @@
 use crate::UNSET_RECT;
 use skia::shaper::run_handler::{Buffer, RunInfo};
+use skia::shaper::BiDiRunIterator;
 use skia::{
-    scalar, Font, FontMetrics, FontMgr, GlyphId, Point, Rect, Shaper, Size, TextBlob,
-    TextBlobBuilder, Vector,
+    scalar, Font, FontMgr, GlyphId, Point, Rect, Shaper, Size, TextBlob, TextBlobBuilder, Vector,
 };
 use std::ptr::NonNull;
+use unicode_bidi::{bidi_class, BidiClass};
+use unicode_segmentation::UnicodeSegmentation;
 
 struct TextBlobAlloc {
     builder: TextBlobBuilder,
@@ -60,14 +62,28 @@ impl TextBlobAlloc {
     }
 }
 
+/// Where a run sits in its line.
+#[derive(Debug, Copy, Clone)]
+struct RunLayout {
+    /// ascent (negative) of the line over all of its runs.
+    ascent: scalar,
+    /// descent of the line over all of its runs.
+    descent: scalar,
+    rtl: bool,
+    /// byte index of the first character of the run, whose text ends where the text passed
+    /// along with the layout does.
+    start: usize,
+}
+
 struct RunHandler<
     'a,
-    C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font) = fn(
+    C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font, RunLayout) = fn(
         &str,
         &[GlyphId],
         &[Point],
         &[u32],
         &Font,
+        RunLayout,
     ),
 > {
     builder: TextBlobAlloc,
@@ -87,7 +103,7 @@ struct RunHandler<
     offset: Point,
 }
 
-impl<C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font)> skia::shaper::RunHandler
+impl<C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font, RunLayout)> skia::shaper::RunHandler
     for RunHandler<'_, C>
 {
     fn begin_line(&mut self) {
@@ -136,6 +152,13 @@ impl<C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font)> skia::shaper::RunHandl
                 positions,
                 clusters,
                 info.font,
+                RunLayout {
+                    // runs in fallback fonts have other metrics, but their carets share the line's.
+                    ascent: self.max_run_ascent,
+                    descent: self.max_run_descent,
+                    rtl: info.bidi_level % 2 == 1,
+                    start: info.utf8_range.start,
+                },
             );
         }
         assert!(0 <= self.cluster_offset);
@@ -160,7 +183,7 @@ impl<C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font)> skia::shaper::RunHandl
     }
 }
 
-impl<'a, C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font)> RunHandler<'a, C> {
+impl<'a, C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font, RunLayout)> RunHandler<'a, C> {
     fn new(text: &'a str) -> Self {
         Self {
             builder: Default::default(),
@@ -188,33 +211,40 @@ impl<'a, C: FnMut(&str, &[GlyphId], &[Point], &[u32], &Font)> RunHandler<'a, C>
         self.builder.make()
     }
 
-    fn final_rect(&self, font: &Font) -> Rect {
+    /// Bounds of the end of the paragraph, which is at the left of the last line when it is
+    /// right-to-left.
+    fn final_rect(&self, font: &Font, rtl: bool) -> Rect {
+        let x = if rtl {
+            self.offset.x - font.size()
+        } else {
+            self.current_position.x
+        };
         if self.max_run_ascent == 0. || self.max_run_descent == 0. {
             let (_, metrics) = font.metrics();
             Rect::from_point_and_size(
-                self.current_position,
+                Point::new(x, self.current_position.y),
                 Size::new(font.size(), metrics.descent - metrics.ascent),
             )
         } else {
             Rect::new(
-                self.current_position.x,
+                x,
                 self.current_position.y + self.max_run_ascent,
-                self.current_position.x + font.size(),
+                x + font.size(),
                 self.current_position.y + self.max_run_descent,
             )
         }
     }
 }
 
-fn selection_box(metrics: &FontMetrics, mut advance: f32, pos: Point) -> Rect {
+fn selection_box(layout: RunLayout, mut advance: f32, pos: Point) -> Rect {
     if advance.abs() < 1. {
         advance = f32::copysign(1., advance);
     }
     Rect::new(
         pos.x,
-        pos.y + metrics.ascent,
+        pos.y + layout.ascent,
         pos.x + advance,
-        pos.y + metrics.descent,
+        pos.y + layout.descent,
     )
 }
 
@@ -225,11 +255,11 @@ fn set_character_bounds(
     positions: &[Point],
     clusters: &[u32],
     font: &Font,
+    layout: RunLayout,
 ) {
     assert!(glyphs.len() > 0);
     assert_eq!(glyphs.len(), clusters.len());
 
-    let (_, metrics) = font.metrics();
     let mut advances = vec![0.; glyphs.len()];
     font.get_widths(glyphs, &mut advances);
 
@@ -265,11 +295,11 @@ fn set_character_bounds(
 
         let mut zipped = cluster_advances.iter().zip(cluster_glyph_positions);
         let (&cluster_advance, &cluster_glyph_position) = zipped.next().unwrap();
-        let mut cluster_box = selection_box(&metrics, cluster_advance, cluster_glyph_position);
+        let mut cluster_box = selection_box(layout, cluster_advance, cluster_glyph_position);
         for (&cluster_advance, &cluster_glyph_position) in zipped {
             // multiple glyphs
             cluster_box.join(selection_box(
-                &metrics,
+                layout,
                 cluster_advance,
                 cluster_glyph_position,
             ));
@@ -280,76 +310,116 @@ fn set_character_bounds(
             cursors[text_begin] = cluster_box;
             continue;
         }
-        let code_point = text.split_at(text_end).0.split_at(text_begin).1;
-        let code_point_count = code_point.chars().count();
-        if code_point_count == 1 {
-            // single codepoint, fast path.
+        let cluster_text = text.split_at(text_end).0.split_at(text_begin).1;
+        let graphemes: Vec<_> = cluster_text.grapheme_indices(true).collect();
+        if graphemes.len() == 1 {
+            // single grapheme cluster (e.g. ZWJ emoji sequence), fast path.
             cursors[text_begin] = cluster_box;
             continue;
         }
 
-        let width = cluster_box.width() / code_point_count as f32;
+        // ligature: split the cluster evenly between its grapheme clusters, which run from right
+        // to left in a right-to-left run.
+        let width = cluster_box.width() / graphemes.len() as f32;
         assert!(width > 0.);
         let base = Rect {
             right: cluster_box.left + width,
             ..cluster_box
         };
-        let mut cursors = &mut cursors[text_begin..];
-        for (i, (j, _)) in code_point.char_indices().enumerate() {
-            cursors[j] = base.with_offset(Vector::new(width * i as f32, 0.));
+        let cursors = &mut cursors[text_begin..];
+        for (i, &(j, _)) in graphemes.iter().enumerate() {
+            let column = if layout.rtl {
+                graphemes.len() - 1 - i
+            } else {
+                i
+            };
+            cursors[j] = base.with_offset(Vector::new(width * column as f32, 0.));
         }
     }
 }
 
+#[derive(Clone)]
 pub struct ShapeResult {
     pub blob: Option<TextBlob>,
     pub line_break_offsets: Vec<usize>,
     pub glyph_bounds: Vec<Rect>,
     pub word_breaks: Vec<bool>,
+    /// whether the grapheme cluster at each byte is right-to-left, and then whether the
+    /// paragraph is.
+    pub rtl: Vec<bool>,
     pub vertical_advance: i32,
 }
 
+/// Base embedding level of a paragraph from its first strong character outside isolates
+/// (UAX #9 rules P2 and P3): 1 for right-to-left, 0 for left-to-right or no strong character.
+pub fn paragraph_level(text: impl IntoIterator<Item = char>) -> u8 {
+    let mut isolates = 0usize;
+    for c in text {
+        match bidi_class(c) {
+            BidiClass::LRI | BidiClass::RLI | BidiClass::FSI => isolates += 1,
+            BidiClass::PDI => isolates = isolates.saturating_sub(1),
+            BidiClass::L if isolates == 0 => return 0,
+            BidiClass::R | BidiClass::AL if isolates == 0 => return 1,
+            _ => {}
+        }
+    }
+    0
+}
+
 pub fn shape(text: &str, font: &Font, font_mgr: FontMgr, locale: &str, width: f32) -> ShapeResult {
     let height = font.spacing();
-    let vertical_advance = height.ceil() as _;
 
     let shaper = Shaper::new_shape_then_wrap(None).unwrap();
+    let level = paragraph_level(text.chars());
     let mut glyph_bounds = vec![UNSET_RECT; text.len()];
-    // cursors.splice(.., std::iter::repeat(UNSET_RECT).take(text.len()));
+    let mut rtl = vec![false; text.len()];
+    rtl.push(level % 2 == 1);
     let mut handler = RunHandler::new(text);
-    handler.set_run_callback(|text, glyphs, positions, clusters, font| {
-        set_character_bounds(&mut glyph_bounds, text, glyphs, positions, clusters, font)
+    handler.set_run_callback(|text, glyphs, positions, clusters, font, layout| {
+        // the whole run, so that the grapheme clusters inside a ligature get its direction too.
+        rtl[layout.start..text.len()].fill(layout.rtl);
+        set_character_bounds(
+            &mut glyph_bounds,
+            text,
+            glyphs,
+            positions,
+            clusters,
+            font,
+            layout,
+        )
     });
 
-    /*
-    let handler = RunHandler::new(utf8_text);
-
-    handler.set_run_callback();
-
-    const BIDI_LEVEL_LTR: u8 = 0;
-    // for bidirectional text (such as arabian)
-    let mut bidi = Shaper::new_bidi_run_iterator(utf8_text, BIDI_LEVEL_LTR).unwrap();
-    // for multiple languages
-    let mut lang = Shaper::new_std_language_run_iterator(utf8_text).unwrap();
-    // for multiple charsets
-    let mut script = Shaper::new_hb_icu_script_run_iterator(utf8_text);
-    // for multiple fonts
-    let mut font_runs = Shaper::new_font_mgr_run_iterator(utf8_text, font, Some(font_mgr));
-
+    // characters missing from `font` are shaped with a fallback font that has them.
+    let mut font_runs = Shaper::new_font_mgr_run_iterator(text, font, font_mgr);
+    // runs of the other direction are reordered within each line. Without ICU the whole paragraph
+    // is laid out in its base direction.
+    let mut icu_bidi;
+    let mut trivial_bidi;
+    let bidi: &mut BiDiRunIterator = match Shaper::new_bidi_run_iterator(text, level) {
+        Some(iterator) => {
+            icu_bidi = iterator;
+            &mut icu_bidi
+        }
+        None => {
+            trivial_bidi = Shaper::new_trivial_bidi_run_iterator(level, text.len());
+            &mut trivial_bidi
+        }
+    };
+    let mut script = Shaper::new_hb_icu_script_run_iterator(text);
+    let mut language = Shaper::new_trivial_language_run_iterator(locale, text.len());
     shaper.shape_with_iterators(
-        utf8_text,
+        text,
         &mut font_runs,
-        &mut bidi,
+        bidi,
         &mut script,
-        &mut lang,
+        &mut language,
         width,
-        &mut TextBlobBuilderRunHandler::new(),
-    );*/
-
-    shaper.shape(text, font, true, width, &mut handler);
+        &mut handler,
+    );
 
     let blob = handler.make_blob();
-    let final_rect = handler.final_rect(font);
+    let final_rect = handler.final_rect(font, level % 2 == 1);
+    let vertical_advance = handler.offset.y.max(height).ceil() as _;
     let line_end_offsets = handler.line_end_offsets;
 
     let mut line_break_offsets = vec![];
@@ -358,13 +428,27 @@ pub fn shape(text: &str, font: &Font, font_mgr: FontMgr, locale: &str, width: f3
         line_break_offsets.pop();
     }
     glyph_bounds.push(final_rect);
-    let word_breaks = (0..text.len()).map(|i| text.is_char_boundary(i)).collect();
+    // one caret rect per grapheme cluster, even if the shaper split it into several clusters.
+    for (i, grapheme) in text.grapheme_indices(true) {
+        for j in i + 1..i + grapheme.len() {
+            let bounds = std::mem::replace(&mut glyph_bounds[j], UNSET_RECT);
+            if bounds != UNSET_RECT {
+                glyph_bounds[i].join(bounds);
+            }
+        }
+    }
+    // UAX #29 word boundaries.
+    let mut word_breaks = vec![false; text.len()];
+    for (i, _) in text.split_word_bound_indices() {
+        word_breaks[i] = true;
+    }
 
     ShapeResult {
         blob,
         line_break_offsets,
         glyph_bounds,
         word_breaks,
+        rtl,
         vertical_advance,
     }
 }
@@ -0,0 +1,84 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use ropey::Rope;
+use skia::{Font, FontMgr};
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    app::UserEvent,
+    editor,
+    shape::{self, ShapeResult},
+};
+
+/// Paragraphs to shape against a snapshot of the text.
+pub struct Job {
+    /// layout generation of the editor when the job was made.
+    pub generation: u64,
+    pub text: Rope,
+    /// paragraph index and revision of each paragraph to shape.
+    pub paragraphs: Vec<(usize, u64)>,
+    pub font: Font,
+    pub locale: &'static str,
+    pub width: f32,
+}
+
+pub struct Shaped {
+    pub generation: u64,
+    pub paragraph_index: usize,
+    pub revision: u64,
+    pub result: ShapeResult,
+}
+
+/// Shapes paragraphs on a background thread and sends each result to the event loop as a
+/// `UserEvent::Shaped`.
+pub struct ShapeWorker {
+    jobs: Sender<Job>,
+}
+
+impl ShapeWorker {
+    pub fn new(proxy: EventLoopProxy<UserEvent>) -> Self {
+        let (jobs, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("shaper".into())
+            .spawn(move || run(receiver, proxy))
+            .unwrap();
+        Self { jobs }
+    }
+
+    pub fn send(&self, job: Job) {
+        // the thread only stops when the event loop is gone.
+        let _ = self.jobs.send(job);
+    }
+}
+
+fn run(receiver: Receiver<Job>, proxy: EventLoopProxy<UserEvent>) {
+    // a FontMgr can't be sent between threads, so the worker uses its own.
+    let font_mgr = FontMgr::new();
+    while let Ok(job) = receiver.recv() {
+        let mut jobs = vec![job];
+        jobs.extend(receiver.try_iter());
+        // results of older generations would be dropped anyway.
+        let generation = jobs.iter().map(|job| job.generation).max().unwrap();
+        for job in jobs.iter().filter(|job| job.generation == generation) {
+            for &(paragraph_index, revision) in &job.paragraphs {
+                let result = shape::shape(
+                    &editor::paragraph(&job.text, paragraph_index),
+                    &job.font,
+                    font_mgr.clone(),
+                    job.locale,
+                    job.width,
+                );
+                let shaped = Shaped {
+                    generation,
+                    paragraph_index,
+                    revision,
+                    result,
+                };
+                if proxy.send_event(UserEvent::Shaped(shaped)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}